@@ -0,0 +1,375 @@
+use std::fmt;
+
+/// The memory bank controller on a cartridge, which decides how ROM
+/// and RAM banks are mapped into the address space.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Mapper {
+    RomOnly,
+    Mbc1,
+    Mbc2,
+    Mmm01,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    PocketCamera,
+    Tama5,
+    HuC3,
+    HuC1,
+}
+
+/// The cartridge type byte at 0x0147, describing the mapper and any
+/// extra hardware on the cartridge.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct CartridgeType {
+    pub code: u8,
+    pub mapper: Mapper,
+    pub ram: bool,
+    pub battery: bool,
+    pub timer: bool,
+    pub rumble: bool,
+    pub sensor: bool,
+}
+
+impl CartridgeType {
+    pub fn from_code(code: u8) -> Option<CartridgeType> {
+        use self::Mapper::*;
+
+        // (mapper, ram, battery, timer, rumble, sensor)
+        let (mapper, ram, battery, timer, rumble, sensor) = match code {
+            0x00 => (RomOnly, false, false, false, false, false),
+            0x01 => (Mbc1, false, false, false, false, false),
+            0x02 => (Mbc1, true, false, false, false, false),
+            0x03 => (Mbc1, true, true, false, false, false),
+            0x05 => (Mbc2, false, false, false, false, false),
+            0x06 => (Mbc2, false, true, false, false, false),
+            0x08 => (RomOnly, true, false, false, false, false),
+            0x09 => (RomOnly, true, true, false, false, false),
+            0x0B => (Mmm01, false, false, false, false, false),
+            0x0C => (Mmm01, true, false, false, false, false),
+            0x0D => (Mmm01, true, true, false, false, false),
+            0x0F => (Mbc3, false, true, true, false, false),
+            0x10 => (Mbc3, true, true, true, false, false),
+            0x11 => (Mbc3, false, false, false, false, false),
+            0x12 => (Mbc3, true, false, false, false, false),
+            0x13 => (Mbc3, true, true, false, false, false),
+            0x19 => (Mbc5, false, false, false, false, false),
+            0x1A => (Mbc5, true, false, false, false, false),
+            0x1B => (Mbc5, true, true, false, false, false),
+            0x1C => (Mbc5, false, false, false, true, false),
+            0x1D => (Mbc5, true, false, false, true, false),
+            0x1E => (Mbc5, true, true, false, true, false),
+            0x20 => (Mbc6, false, false, false, false, false),
+            0x22 => (Mbc7, true, true, false, true, true),
+            0xFC => (PocketCamera, false, false, false, false, false),
+            0xFD => (Tama5, false, false, false, false, false),
+            0xFE => (HuC3, false, false, false, false, false),
+            0xFF => (HuC1, true, true, false, false, false),
+            _ => return None,
+        };
+
+        Some(CartridgeType {
+            code,
+            mapper,
+            ram,
+            battery,
+            timer,
+            rumble,
+            sensor,
+        })
+    }
+}
+
+// Use the names from the Pan Docs, e.g. "MBC3+TIMER+RAM+BATTERY".
+impl fmt::Display for CartridgeType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self.mapper {
+            Mapper::RomOnly if self.ram => "ROM",
+            Mapper::RomOnly => "ROM ONLY",
+            Mapper::Mbc1 => "MBC1",
+            Mapper::Mbc2 => "MBC2",
+            Mapper::Mmm01 => "MMM01",
+            Mapper::Mbc3 => "MBC3",
+            Mapper::Mbc5 => "MBC5",
+            Mapper::Mbc6 => "MBC6",
+            Mapper::Mbc7 => "MBC7",
+            Mapper::PocketCamera => "POCKET CAMERA",
+            Mapper::Tama5 => "BANDAI TAMA5",
+            Mapper::HuC3 => "HuC3",
+            Mapper::HuC1 => "HuC1",
+        };
+        write!(f, "{}", name)?;
+
+        if self.sensor {
+            write!(f, "+SENSOR")?;
+        }
+        if self.timer {
+            write!(f, "+TIMER")?;
+        }
+        if self.rumble {
+            write!(f, "+RUMBLE")?;
+        }
+        if self.ram {
+            write!(f, "+RAM")?;
+        }
+        if self.battery {
+            write!(f, "+BATTERY")?;
+        }
+        Ok(())
+    }
+}
+
+/// Byte 0x0143: whether the game uses Game Boy Color features.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum CgbSupport {
+    None,
+    Compatible,
+    Only,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+}
+
+/// The cartridge header, stored at 0x0100-0x014F of every ROM.
+#[derive(Debug)]
+pub struct CartridgeHeader {
+    pub title: String,
+    // Only present on later cartridges, otherwise empty.
+    pub manufacturer_code: String,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub licensee_code: String,
+    // None if the code isn't one we know. The raw codes are kept so
+    // unusual dumps can still be described.
+    pub cartridge_type: Option<CartridgeType>,
+    pub cartridge_type_code: u8,
+    // Sizes in bytes, or None if the code is unknown.
+    pub rom_size: Option<usize>,
+    pub rom_size_code: u8,
+    pub ram_size: Option<usize>,
+    pub ram_size_code: u8,
+    pub destination: Destination,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    // Checksums we calculated from the ROM contents.
+    pub computed_header_checksum: u8,
+    pub computed_global_checksum: u16,
+}
+
+impl CartridgeHeader {
+    pub fn header_checksum_valid(&self) -> bool {
+        self.header_checksum == self.computed_header_checksum
+    }
+
+    /// The global checksum isn't checked by real hardware, so many
+    /// homebrew ROMs leave it unset.
+    pub fn global_checksum_valid(&self) -> bool {
+        self.global_checksum == self.computed_global_checksum
+    }
+}
+
+/// The header is in 0100-014F, so ROMs shorter than this don't have one.
+pub const HEADER_END: usize = 0x0150;
+
+/// Parse the header of a ROM image. This only fails if the ROM is too
+/// small to have a header, so that broken or unusual headers can
+/// still be inspected.
+pub fn parse_header(rom: &[u8]) -> Result<CartridgeHeader, String> {
+    if rom.len() < HEADER_END {
+        return Err(format!("ROM is too small to have a header ({} bytes)", rom.len()));
+    }
+
+    let cgb = match rom[0x143] {
+        0xC0 => CgbSupport::Only,
+        0x80 => CgbSupport::Compatible,
+        _ => CgbSupport::None,
+    };
+
+    // Later cartridges shortened the title to make room for a
+    // manufacturer code and the CGB flag.
+    let (title, manufacturer_code) = if cgb != CgbSupport::None {
+        let manufacturer = &rom[0x13F..0x143];
+        if manufacturer.iter().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit()) {
+            (decode_ascii(&rom[0x134..0x13F]), decode_ascii(manufacturer))
+        } else {
+            (decode_ascii(&rom[0x134..0x143]), String::new())
+        }
+    } else {
+        (decode_ascii(&rom[0x134..0x144]), String::new())
+    };
+
+    // 0x33 means the licensee is given by the two byte code at 0x0144.
+    let licensee_code = if rom[0x14B] == 0x33 {
+        decode_ascii(&rom[0x144..0x146])
+    } else {
+        format!("{:02X}", rom[0x14B])
+    };
+
+    let rom_size = match rom[0x148] {
+        code @ 0x00..=0x08 => Some(0x8000 << code),
+        _ => None,
+    };
+
+    let ram_size = match rom[0x149] {
+        0x00 => Some(0),
+        0x01 => Some(0x800),
+        0x02 => Some(0x2000),
+        0x03 => Some(0x8000),
+        0x04 => Some(0x20000),
+        0x05 => Some(0x10000),
+        _ => None,
+    };
+
+    let destination = if rom[0x14A] == 0x00 {
+        Destination::Japan
+    } else {
+        Destination::Overseas
+    };
+
+    Ok(CartridgeHeader {
+        title,
+        manufacturer_code,
+        cgb,
+        sgb: rom[0x146] == 0x03,
+        licensee_code,
+        cartridge_type: CartridgeType::from_code(rom[0x147]),
+        cartridge_type_code: rom[0x147],
+        rom_size,
+        rom_size_code: rom[0x148],
+        ram_size,
+        ram_size_code: rom[0x149],
+        destination,
+        version: rom[0x14C],
+        header_checksum: rom[0x14D],
+        global_checksum: ((rom[0x14E] as u16) << 8) | rom[0x14F] as u16,
+        computed_header_checksum: compute_header_checksum(rom),
+        computed_global_checksum: compute_global_checksum(rom),
+    })
+}
+
+/// Read a NUL-padded ASCII string. Anything unprintable ends the
+/// string.
+fn decode_ascii(bytes: &[u8]) -> String {
+    bytes.iter()
+        .take_while(|b| **b >= 0x20 && **b < 0x7F)
+        .map(|b| *b as char)
+        .collect::<String>()
+        .trim_end()
+        .to_owned()
+}
+
+/// The checksum over 0x0134-0x014C. The boot ROM refuses to start
+/// the game if this doesn't match.
+fn compute_header_checksum(rom: &[u8]) -> u8 {
+    rom[0x134..0x14D].iter().fold(0u8, |sum, b| sum.wrapping_sub(*b).wrapping_sub(1))
+}
+
+/// The sum of every byte in the ROM, excluding the global checksum
+/// itself.
+fn compute_global_checksum(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|&(i, _)| i != 0x14E && i != 0x14F)
+        .fold(0u16, |sum, (_, b)| sum.wrapping_add(*b as u16))
+}
+
+// Build a 32 KiB ROM with a plausible header.
+#[cfg(test)]
+fn test_rom(title: &str, cartridge_type: u8) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x134..0x134 + title.len()].copy_from_slice(title.as_bytes());
+    rom[0x147] = cartridge_type;
+    rom[0x14B] = 0x01;
+
+    rom[0x14D] = compute_header_checksum(&rom);
+    let global = compute_global_checksum(&rom);
+    rom[0x14E] = (global >> 8) as u8;
+    rom[0x14F] = global as u8;
+    rom
+}
+
+#[test]
+fn parse_basic_header() {
+    let header = parse_header(&test_rom("TETRIS", 0x00)).unwrap();
+
+    assert_eq!(header.title, "TETRIS");
+    assert_eq!(header.manufacturer_code, "");
+    assert_eq!(header.cgb, CgbSupport::None);
+    assert!(!header.sgb);
+    assert_eq!(header.licensee_code, "01");
+    assert_eq!(header.cartridge_type.unwrap().mapper, Mapper::RomOnly);
+    assert_eq!(header.rom_size, Some(0x8000));
+    assert_eq!(header.ram_size, Some(0));
+    assert_eq!(header.destination, Destination::Japan);
+    assert!(header.header_checksum_valid());
+    assert!(header.global_checksum_valid());
+}
+
+#[test]
+fn parse_cgb_header() {
+    let mut rom = test_rom("POKEMON_SLV", 0x10);
+    rom[0x13F..0x143].copy_from_slice(b"AAXE");
+    rom[0x143] = 0x80;
+    rom[0x144..0x146].copy_from_slice(b"01");
+    rom[0x146] = 0x03;
+    rom[0x148] = 0x06;
+    rom[0x149] = 0x03;
+    rom[0x14A] = 0x01;
+    rom[0x14B] = 0x33;
+
+    let header = parse_header(&rom).unwrap();
+    assert_eq!(header.title, "POKEMON_SLV");
+    assert_eq!(header.manufacturer_code, "AAXE");
+    assert_eq!(header.cgb, CgbSupport::Compatible);
+    assert!(header.sgb);
+    assert_eq!(header.licensee_code, "01");
+    assert_eq!(header.rom_size, Some(2 * 1024 * 1024));
+    assert_eq!(header.ram_size, Some(32 * 1024));
+    assert_eq!(header.destination, Destination::Overseas);
+
+    assert_eq!(format!("{}", header.cartridge_type.unwrap()), "MBC3+TIMER+RAM+BATTERY");
+}
+
+#[test]
+fn parse_bad_checksums() {
+    let mut rom = test_rom("TETRIS", 0x00);
+    rom[0x14D] ^= 0xFF;
+    rom[0x200] = 0x42;
+
+    let header = parse_header(&rom).unwrap();
+    assert!(!header.header_checksum_valid());
+    assert!(!header.global_checksum_valid());
+}
+
+#[test]
+fn parse_too_small() {
+    assert!(parse_header(&[0; 0x100]).is_err());
+}
+
+#[test]
+fn parse_unknown_codes() {
+    let mut rom = test_rom("ODD", 0x04);
+    rom[0x148] = 0x52;
+    rom[0x149] = 0x07;
+
+    let header = parse_header(&rom).unwrap();
+    assert_eq!(header.title, "ODD");
+    assert_eq!(header.cartridge_type, None);
+    assert_eq!(header.cartridge_type_code, 0x04);
+    assert_eq!(header.rom_size, None);
+    assert_eq!(header.rom_size_code, 0x52);
+    assert_eq!(header.ram_size, None);
+    assert_eq!(header.ram_size_code, 0x07);
+}
+
+#[test]
+fn cartridge_type_names() {
+    assert_eq!(format!("{}", CartridgeType::from_code(0x00).unwrap()), "ROM ONLY");
+    assert_eq!(format!("{}", CartridgeType::from_code(0x09).unwrap()), "ROM+RAM+BATTERY");
+    assert_eq!(format!("{}", CartridgeType::from_code(0x1E).unwrap()),
+               "MBC5+RUMBLE+RAM+BATTERY");
+}
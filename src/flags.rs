@@ -0,0 +1,122 @@
+use std::fmt;
+
+const ZERO: u8 = 0x80;
+const SUBTRACT: u8 = 0x40;
+const HALF_CARRY: u8 = 0x20;
+const CARRY: u8 = 0x10;
+
+/// The F register. Only the upper nibble holds flags, the lower
+/// nibble always reads as zero.
+#[derive(Clone,Copy,PartialEq,Eq,Default)]
+pub struct Flags(u8);
+
+impl Flags {
+    pub fn new(zero: bool, subtract: bool, half_carry: bool, carry: bool) -> Flags {
+        let mut flags = Flags(0);
+        flags.set(ZERO, zero);
+        flags.set(SUBTRACT, subtract);
+        flags.set(HALF_CARRY, half_carry);
+        flags.set(CARRY, carry);
+        flags
+    }
+
+    /// Build flags from the F register value. The lower nibble can't
+    /// be set, so it's discarded.
+    pub fn from_byte(byte: u8) -> Flags {
+        Flags(byte & 0xF0)
+    }
+
+    pub fn to_byte(self) -> u8 {
+        self.0
+    }
+
+    /// Z: the result of the last operation was zero.
+    pub fn zero(&self) -> bool {
+        self.0 & ZERO != 0
+    }
+
+    /// N: the last operation was a subtraction. Only used by DAA.
+    pub fn subtract(&self) -> bool {
+        self.0 & SUBTRACT != 0
+    }
+
+    /// H: the last operation carried out of the lower nibble. Only
+    /// used by DAA.
+    pub fn half_carry(&self) -> bool {
+        self.0 & HALF_CARRY != 0
+    }
+
+    /// C: the last operation carried out of the top bit (or
+    /// borrowed, for subtraction).
+    pub fn carry(&self) -> bool {
+        self.0 & CARRY != 0
+    }
+
+    pub fn set_zero(&mut self, value: bool) {
+        self.set(ZERO, value);
+    }
+
+    pub fn set_subtract(&mut self, value: bool) {
+        self.set(SUBTRACT, value);
+    }
+
+    pub fn set_half_carry(&mut self, value: bool) {
+        self.set(HALF_CARRY, value);
+    }
+
+    pub fn set_carry(&mut self, value: bool) {
+        self.set(CARRY, value);
+    }
+
+    fn set(&mut self, flag: u8, value: bool) {
+        if value {
+            self.0 |= flag;
+        } else {
+            self.0 &= !flag;
+        }
+    }
+}
+
+// Show flags the way debuggers usually do, e.g. "Z-H-" for zero and
+// half carry set.
+impl fmt::Debug for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}{}{}",
+               if self.zero() { 'Z' } else { '-' },
+               if self.subtract() { 'N' } else { '-' },
+               if self.half_carry() { 'H' } else { '-' },
+               if self.carry() { 'C' } else { '-' })
+    }
+}
+
+#[test]
+fn flags_new() {
+    let flags = Flags::new(true, false, true, false);
+    assert!(flags.zero());
+    assert!(!flags.subtract());
+    assert!(flags.half_carry());
+    assert!(!flags.carry());
+    assert_eq!(flags.0, 0xA0);
+}
+
+#[test]
+fn flags_setters() {
+    let mut flags = Flags::new(true, true, true, true);
+    flags.set_zero(false);
+    flags.set_subtract(false);
+    flags.set_half_carry(false);
+    flags.set_carry(false);
+    assert_eq!(flags, Flags::default());
+}
+
+#[test]
+fn flags_from_byte() {
+    let flags = Flags::from_byte(0xFF);
+    assert_eq!(flags.to_byte(), 0xF0);
+}
+
+#[test]
+fn flags_debug() {
+    let flags = Flags::new(true, false, true, false);
+    assert_eq!(format!("{:?}", flags), "Z-H-");
+}
@@ -1,10 +1,23 @@
+use std::cmp::min;
 use std::fmt;
 use std::num::Wrapping;
 
+use flags::Flags;
+use interrupts::{highest_priority, IE_ADDRESS, IF_ADDRESS};
+use joypad::Buttons;
+use link::Link;
+use mbc::RomOnly;
+use memory::Memory;
+use model::Model;
+use save::SaveFile;
+use serial::SerialOutput;
+use timer::DIV_ADDRESS;
+
 use self::Instruction::*;
 use self::Register8::*;
 use self::Register16::*;
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
     // Generic registers.
     a: Wrapping<u8>,
@@ -15,37 +28,56 @@ pub struct CPU {
     h: Wrapping<u8>,
     l: Wrapping<u8>,
 
-    flags: Wrapping<u8>,
+    flags: Flags,
 
     // Program state.
     pc: Wrapping<u16>,
     sp: Wrapping<u16>,
 
-    // Clock.
-    m: Wrapping<u8>,
-    t: Wrapping<u8>,
-
-    memory: [u8; 65536],
+    // Interrupt master enable.
+    ime: bool,
+    // EI only sets IME after the following instruction.
+    ime_scheduled: bool,
+
+    // Low power states, entered by HALT and STOP.
+    halted: bool,
+    stopped: bool,
+    // Set when HALT is executed with IME clear and an interrupt
+    // already pending. The CPU then fails to increment PC after
+    // reading the next opcode.
+    halt_bug: bool,
+
+    // Total machine cycles (M-cycles) executed. One M-cycle is four
+    // clock cycles.
+    cycles: u64,
+
+    // Print each instruction and the CPU state as we execute.
+    trace: bool,
+    // Stop running once this many M-cycles have been executed.
+    cycle_limit: Option<u64>,
+
+    memory: Memory,
 }
 
 impl fmt::Debug for CPU {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        try!(write!(f, "CPU "));
-        try!(write!(f, "A: {:02X} ", self.a.0));
-        try!(write!(f, "B: {:02X} ", self.b.0));
-        try!(write!(f, "C: {:02X} ", self.c.0));
-        try!(write!(f, "D: {:02X} ", self.d.0));
-        try!(write!(f, "E: {:02X} ", self.e.0));
-        try!(write!(f, "H: {:02X} ", self.h.0));
-        try!(write!(f, "L: {:02X} ", self.l.0));
-        try!(write!(f, "Flags: {:02X} ", self.flags.0));
-        try!(write!(f, "PC: {:02X} ", self.pc.0));
-        try!(write!(f, "SP: {:04X} ", self.sp.0));
-        write!(f, "Clock M:{:02X} T:{:02X}", self.m.0, self.t.0)
+        write!(f, "CPU ")?;
+        write!(f, "A: {:02X} ", self.a.0)?;
+        write!(f, "B: {:02X} ", self.b.0)?;
+        write!(f, "C: {:02X} ", self.c.0)?;
+        write!(f, "D: {:02X} ", self.d.0)?;
+        write!(f, "E: {:02X} ", self.e.0)?;
+        write!(f, "H: {:02X} ", self.h.0)?;
+        write!(f, "L: {:02X} ", self.l.0)?;
+        write!(f, "Flags: {:?} ", self.flags)?;
+        write!(f, "PC: {:02X} ", self.pc.0)?;
+        write!(f, "SP: {:04X} ", self.sp.0)?;
+        write!(f, "IME: {} ", self.ime as u8)?;
+        write!(f, "Cycles: {}", self.cycles)
     }
 }
 
-#[derive(Debug,PartialEq,Eq)]
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
 pub enum Register8 {
     A,
     B,
@@ -56,32 +88,41 @@ pub enum Register8 {
     L,
 }
 
-#[derive(Debug,PartialEq,Eq)]
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
 pub enum Operand8 {
     Register(Register8),
     MemoryAddress(Register16),
     MemoryAddressWithOffset(Register8, u16),
+    ConstantMemoryAddress(u16),
+    ConstantMemoryAddressWithOffset(u8, u16),
     Immediate(u8),
 }
 
-#[derive(Debug,PartialEq,Eq)]
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
 pub enum Operand16 {
     Register(Register16),
     Immediate(u16),
+    SignedImmediate(i8),
     ConstantMemoryAddress(u16),
+    StackPointerWithOffset(i8),
 }
 
-#[derive(Debug,PartialEq,Eq)]
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
 pub enum Register16 {
     BC,
     DE,
     HL,
     SP,
+    AF,
 }
 
-#[derive(Debug,PartialEq,Eq)]
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
 pub enum Condition {
+    Always,
+    Zero,
     NonZero,
+    Carry,
+    NoCarry,
 }
 
 // The main enum for Z80 instructions. Note that we assume intel
@@ -91,10 +132,21 @@ pub enum Instruction {
     Nop,
     Stop,
     Halt,
+    DisableInterrupts,
+    EnableInterrupts,
     Add(Operand8, Operand8),
+    AddWithCarry(Operand8, Operand8),
     Add16(Operand16, Operand16),
+    Subtract(Operand8),
+    SubtractWithCarry(Operand8, Operand8),
+    And(Operand8),
     Or(Operand8),
     Xor(Operand8),
+    Compare(Operand8),
+    DecimalAdjust,
+    Complement,
+    SetCarryFlag,
+    ComplementCarryFlag,
     Load(Operand8, Operand8),
     Load16(Operand16, Operand16),
     LoadIncrement(Operand8, Operand8),
@@ -105,11 +157,38 @@ pub enum Instruction {
     Decrement16(Operand16),
     RotateLeftWithCarry(Operand8),
     RotateRightWithCarry(Operand8),
+    RotateLeft(Operand8),
+    RotateRight(Operand8),
+    ShiftLeftArithmetic(Operand8),
+    ShiftRightArithmetic(Operand8),
+    ShiftRightLogical(Operand8),
+    Swap(Operand8),
+    // The one byte accumulator rotates (RLCA, RRCA, RLA, RRA). These
+    // differ from their 0xCB counterparts in size and always clear Z.
+    RotateLeftWithCarryA,
+    RotateRightWithCarryA,
+    RotateLeftA,
+    RotateRightA,
     // First argument is 0-7, annoyingly Rust doesn't have a u3 type.
     Bit(u8, Operand8),
+    Reset(u8, Operand8),
+    Set(u8, Operand8),
     JumpRelative(Condition, i8),
+    Jump(Condition, Operand16),
+    Call(Condition, u16),
+    Return(Condition),
+    ReturnFromInterrupt,
+    // Argument is the target address, e.g. 0x38 for RST 38H.
+    Restart(u8),
+    Push(Register16),
+    Pop(Register16),
 }
 
+/// Opcodes that have no instruction assigned on the Game Boy. Real
+/// hardware locks up if it executes any of these.
+pub const ILLEGAL_OPCODES: [u8; 11] =
+    [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD];
+
 pub fn initial_cpu() -> CPU {
     CPU {
         a: Wrapping(0),
@@ -119,13 +198,82 @@ pub fn initial_cpu() -> CPU {
         e: Wrapping(0),
         h: Wrapping(0),
         l: Wrapping(0),
-        flags: Wrapping(0),
+        flags: Flags::default(),
         pc: Wrapping(0),
         sp: Wrapping(0),
-        m: Wrapping(0),
-        t: Wrapping(0),
-        memory: [0; 65536]
+        ime: false,
+        ime_scheduled: false,
+        halted: false,
+        stopped: false,
+        halt_bug: false,
+        cycles: 0,
+        trace: false,
+        cycle_limit: None,
+        memory: Memory::new(Box::new(RomOnly::new(vec![], 0, false))),
+    }
+}
+
+/// Put the CPU in the state the boot ROM leaves it in, just as it
+/// jumps to the cartridge at 0x0100.
+fn skip_boot_rom(cpu: &mut CPU, model: Model) {
+    // The DMG boot ROM's header checksum loop leaves H and C set,
+    // unless the checksum byte is zero.
+    let checksum = cpu.memory.read(0x014D);
+    let dmg_flags = Flags::new(true, false, checksum != 0, checksum != 0);
+    // The AGB boot ROM does an extra INC B, which also clears Z.
+    let agb = model == Model::Agb;
+    let cgb_flags = Flags::new(!agb, false, false, false);
+
+    let (a, flags, bc, de, hl) = match model {
+        Model::Dmg0 => (0x01, Flags::default(), 0xFF13, 0x00C1, 0x8403),
+        Model::Dmg => (0x01, dmg_flags, 0x0013, 0x00D8, 0x014D),
+        Model::Mgb => (0xFF, dmg_flags, 0x0013, 0x00D8, 0x014D),
+        Model::Sgb => (0x01, Flags::default(), 0x0014, 0x0000, 0xC060),
+        Model::Sgb2 => (0xFF, Flags::default(), 0x0014, 0x0000, 0xC060),
+        Model::Cgb | Model::Agb if cpu.memory.read(0x0143) & 0x80 != 0 => {
+            (0x11, cgb_flags, if agb { 0x0100 } else { 0x0000 }, 0xFF56, 0x000D)
+        }
+        Model::Cgb | Model::Agb => {
+            // DMG games get a compatibility palette. For Nintendo
+            // games it's chosen by the title checksum, which is left
+            // in B.
+            let nintendo = cpu.memory.read(0x014B) == 0x01 ||
+                (cpu.memory.read(0x014B) == 0x33 && cpu.memory.read(0x0144) == b'0' &&
+                 cpu.memory.read(0x0145) == b'1');
+            let (b, hl) = if nintendo {
+                let title_checksum = (0x0134..0x0144)
+                    .fold(0u8, |sum, address| sum.wrapping_add(cpu.memory.read(address)));
+                (title_checksum, 0x991A)
+            } else {
+                (0x00, 0x007C)
+            };
+            let b = if agb { b.wrapping_add(1) } else { b };
+            (0x11, cgb_flags, (b as u16) << 8, 0x0008, hl)
+        }
+    };
+
+    cpu.a = Wrapping(a);
+    cpu.flags = flags;
+    write_register16(cpu, BC, bc);
+    write_register16(cpu, DE, de);
+    write_register16(cpu, HL, hl);
+    cpu.sp = Wrapping(0xFFFE);
+    cpu.pc = Wrapping(0x0100);
+    cpu.memory.skip_boot_rom(model);
+}
+
+/// Load a ROM and get the CPU ready to run it. With a boot ROM,
+/// execution starts in the boot ROM at 0x0000. Otherwise we start at
+/// 0x0100, as if the boot ROM for `model` had already run.
+pub fn load_cartridge(bytes: &[u8], model: Model, boot_rom: Option<&[u8]>)
+                      -> Result<CPU, String> {
+    let mut cpu = initial_cpu();
+    cpu.memory = Memory::from_rom(bytes.to_vec())?;
+    match boot_rom {
+        Some(boot_rom) => cpu.memory.map_boot_rom(boot_rom.to_vec(), model)?,
+        None => skip_boot_rom(&mut cpu, model),
     }
+    Ok(cpu)
 }
 
 // Get a mutable reference to targeted register.
@@ -142,8 +290,30 @@ fn register8(cpu: &mut CPU, target: Register8) -> &mut Wrapping<u8> {
 }
 
 /// Given a position in a byte array, return the instruction at that
-/// point. Based on http://imrannazar.com/Gameboy-Z80-Opcode-Map .
+/// point. Returns None if the opcode is illegal or its operands run
+/// past the end of the array.
 pub fn decode(bytes: &[u8], offset: usize) -> Option<Instruction> {
+    if offset >= bytes.len() {
+        return None;
+    }
+
+    // Instructions are at most three bytes. Decode from a zero-padded
+    // copy so we never index past the end, then check the instruction
+    // actually fitted.
+    let available = min(bytes.len() - offset, 3);
+    let mut padded = [0; 3];
+    padded[..available].copy_from_slice(&bytes[offset..offset + available]);
+
+    let instr = decode_complete(&padded, 0)?;
+    if instr_size(&instr) > bytes.len() - offset {
+        return None;
+    }
+    Some(instr)
+}
+
+/// Decode an instruction whose operand bytes are all present. Based on
+/// http://imrannazar.com/Gameboy-Z80-Opcode-Map .
+fn decode_complete(bytes: &[u8], offset: usize) -> Option<Instruction> {
     match bytes[offset] {
         0x00 => Some(Nop),
         0x01 => {
@@ -158,7 +328,7 @@ pub fn decode(bytes: &[u8], offset: usize) -> Option<Instruction> {
             Some(Load(Operand8::Register(B),
                       decode_immediate8(&bytes[offset + 1..])))
         }
-        0x07 => Some(RotateLeftWithCarry(Operand8::Register(A))),
+        0x07 => Some(RotateLeftWithCarryA),
         0x08 => {
             Some(Load16(decode_constant_address(&bytes[offset + 1..]),
                         Operand16::Register(SP)))
@@ -170,9 +340,9 @@ pub fn decode(bytes: &[u8], offset: usize) -> Option<Instruction> {
         0x0D => Some(Decrement(Operand8::Register(C))),
         0x0E => {
             Some(Load(Operand8::Register(C),
-                      Operand8::Immediate(bytes[offset + 1])))
+                      decode_immediate8(&bytes[offset + 1..])))
         }
-        0x0F => Some(RotateRightWithCarry(Operand8::Register(A))),
+        0x0F => Some(RotateRightWithCarryA),
         0x10 => Some(Stop),
         0x11 => {
             Some(Load16(Operand16::Register(DE),
@@ -186,14 +356,19 @@ pub fn decode(bytes: &[u8], offset: usize) -> Option<Instruction> {
             Some(Load(Operand8::Register(D),
                       decode_immediate8(&bytes[offset + 1..])))
         }
+        0x17 => Some(RotateLeftA),
+        0x18 => Some(JumpRelative(Condition::Always, bytes[offset + 1] as i8)),
         0x19 => Some(Add16(Operand16::Register(HL), Operand16::Register(DE))),
+        0x1A => Some(Load(Operand8::Register(A), Operand8::MemoryAddress(DE))),
         0x1B => Some(Decrement16(Operand16::Register(DE))),
         0x1C => Some(Increment(Operand8::Register(E))),
         0x1D => Some(Decrement(Operand8::Register(E))),
-        0x20 => {
-            let addr_offset = bytes[offset + 1] as i8;
-            Some(JumpRelative(Condition::NonZero, addr_offset))
+        0x1E => {
+            Some(Load(Operand8::Register(E),
+                      decode_immediate8(&bytes[offset + 1..])))
         }
+        0x1F => Some(RotateRightA),
+        0x20 => Some(JumpRelative(Condition::NonZero, bytes[offset + 1] as i8)),
         0x21 => {
             Some(Load16(Operand16::Register(HL),
                         decode_immediate16(&bytes[offset + 1..])))
@@ -206,10 +381,19 @@ pub fn decode(bytes: &[u8], offset: usize) -> Option<Instruction> {
             Some(Load(Operand8::Register(H),
                       decode_immediate8(&bytes[offset + 1..])))
         }
+        0x27 => Some(DecimalAdjust),
+        0x28 => Some(JumpRelative(Condition::Zero, bytes[offset + 1] as i8)),
         0x29 => Some(Add16(Operand16::Register(HL), Operand16::Register(HL))),
+        0x2A => Some(LoadIncrement(Operand8::Register(A), Operand8::MemoryAddress(HL))),
         0x2B => Some(Decrement16(Operand16::Register(HL))),
         0x2C => Some(Increment(Operand8::Register(L))),
         0x2D => Some(Decrement(Operand8::Register(L))),
+        0x2E => {
+            Some(Load(Operand8::Register(L),
+                      decode_immediate8(&bytes[offset + 1..])))
+        }
+        0x2F => Some(Complement),
+        0x30 => Some(JumpRelative(Condition::NoCarry, bytes[offset + 1] as i8)),
         0x31 => {
             Some(Load16(Operand16::Register(SP),
                         decode_immediate16(&bytes[offset + 1..])))
@@ -222,16 +406,82 @@ pub fn decode(bytes: &[u8], offset: usize) -> Option<Instruction> {
             Some(Load(Operand8::MemoryAddress(HL),
                       decode_immediate8(&bytes[offset + 1..])))
         }
+        0x37 => Some(SetCarryFlag),
+        0x38 => Some(JumpRelative(Condition::Carry, bytes[offset + 1] as i8)),
         0x39 => Some(Add16(Operand16::Register(HL), Operand16::Register(SP))),
+        0x3A => Some(LoadDecrement(Operand8::Register(A), Operand8::MemoryAddress(HL))),
         0x3B => Some(Decrement16(Operand16::Register(SP))),
         0x3C => Some(Increment(Operand8::Register(A))),
         0x3D => Some(Decrement(Operand8::Register(A))),
         0x3E => {
             Some(Load(Operand8::Register(A),
-                      Operand8::Immediate(bytes[offset + 1])))
+                      decode_immediate8(&bytes[offset + 1..])))
         }
+        0x3F => Some(ComplementCarryFlag),
+        0x40 => Some(Load(Operand8::Register(B), Operand8::Register(B))),
+        0x41 => Some(Load(Operand8::Register(B), Operand8::Register(C))),
+        0x42 => Some(Load(Operand8::Register(B), Operand8::Register(D))),
+        0x43 => Some(Load(Operand8::Register(B), Operand8::Register(E))),
+        0x44 => Some(Load(Operand8::Register(B), Operand8::Register(H))),
+        0x45 => Some(Load(Operand8::Register(B), Operand8::Register(L))),
+        0x46 => Some(Load(Operand8::Register(B), Operand8::MemoryAddress(HL))),
+        0x47 => Some(Load(Operand8::Register(B), Operand8::Register(A))),
+        0x48 => Some(Load(Operand8::Register(C), Operand8::Register(B))),
+        0x49 => Some(Load(Operand8::Register(C), Operand8::Register(C))),
+        0x4A => Some(Load(Operand8::Register(C), Operand8::Register(D))),
+        0x4B => Some(Load(Operand8::Register(C), Operand8::Register(E))),
+        0x4C => Some(Load(Operand8::Register(C), Operand8::Register(H))),
+        0x4D => Some(Load(Operand8::Register(C), Operand8::Register(L))),
+        0x4E => Some(Load(Operand8::Register(C), Operand8::MemoryAddress(HL))),
+        0x4F => Some(Load(Operand8::Register(C), Operand8::Register(A))),
+        0x50 => Some(Load(Operand8::Register(D), Operand8::Register(B))),
+        0x51 => Some(Load(Operand8::Register(D), Operand8::Register(C))),
+        0x52 => Some(Load(Operand8::Register(D), Operand8::Register(D))),
+        0x53 => Some(Load(Operand8::Register(D), Operand8::Register(E))),
+        0x54 => Some(Load(Operand8::Register(D), Operand8::Register(H))),
+        0x55 => Some(Load(Operand8::Register(D), Operand8::Register(L))),
+        0x56 => Some(Load(Operand8::Register(D), Operand8::MemoryAddress(HL))),
+        0x57 => Some(Load(Operand8::Register(D), Operand8::Register(A))),
+        0x58 => Some(Load(Operand8::Register(E), Operand8::Register(B))),
+        0x59 => Some(Load(Operand8::Register(E), Operand8::Register(C))),
+        0x5A => Some(Load(Operand8::Register(E), Operand8::Register(D))),
+        0x5B => Some(Load(Operand8::Register(E), Operand8::Register(E))),
+        0x5C => Some(Load(Operand8::Register(E), Operand8::Register(H))),
+        0x5D => Some(Load(Operand8::Register(E), Operand8::Register(L))),
+        0x5E => Some(Load(Operand8::Register(E), Operand8::MemoryAddress(HL))),
+        0x5F => Some(Load(Operand8::Register(E), Operand8::Register(A))),
+        0x60 => Some(Load(Operand8::Register(H), Operand8::Register(B))),
+        0x61 => Some(Load(Operand8::Register(H), Operand8::Register(C))),
+        0x62 => Some(Load(Operand8::Register(H), Operand8::Register(D))),
+        0x63 => Some(Load(Operand8::Register(H), Operand8::Register(E))),
+        0x64 => Some(Load(Operand8::Register(H), Operand8::Register(H))),
+        0x65 => Some(Load(Operand8::Register(H), Operand8::Register(L))),
+        0x66 => Some(Load(Operand8::Register(H), Operand8::MemoryAddress(HL))),
+        0x67 => Some(Load(Operand8::Register(H), Operand8::Register(A))),
+        0x68 => Some(Load(Operand8::Register(L), Operand8::Register(B))),
+        0x69 => Some(Load(Operand8::Register(L), Operand8::Register(C))),
+        0x6A => Some(Load(Operand8::Register(L), Operand8::Register(D))),
+        0x6B => Some(Load(Operand8::Register(L), Operand8::Register(E))),
+        0x6C => Some(Load(Operand8::Register(L), Operand8::Register(H))),
+        0x6D => Some(Load(Operand8::Register(L), Operand8::Register(L))),
+        0x6E => Some(Load(Operand8::Register(L), Operand8::MemoryAddress(HL))),
+        0x6F => Some(Load(Operand8::Register(L), Operand8::Register(A))),
+        0x70 => Some(Load(Operand8::MemoryAddress(HL), Operand8::Register(B))),
+        0x71 => Some(Load(Operand8::MemoryAddress(HL), Operand8::Register(C))),
+        0x72 => Some(Load(Operand8::MemoryAddress(HL), Operand8::Register(D))),
+        0x73 => Some(Load(Operand8::MemoryAddress(HL), Operand8::Register(E))),
+        0x74 => Some(Load(Operand8::MemoryAddress(HL), Operand8::Register(H))),
+        0x75 => Some(Load(Operand8::MemoryAddress(HL), Operand8::Register(L))),
         0x76 => Some(Halt),
         0x77 => Some(Load(Operand8::MemoryAddress(HL), Operand8::Register(A))),
+        0x78 => Some(Load(Operand8::Register(A), Operand8::Register(B))),
+        0x79 => Some(Load(Operand8::Register(A), Operand8::Register(C))),
+        0x7A => Some(Load(Operand8::Register(A), Operand8::Register(D))),
+        0x7B => Some(Load(Operand8::Register(A), Operand8::Register(E))),
+        0x7C => Some(Load(Operand8::Register(A), Operand8::Register(H))),
+        0x7D => Some(Load(Operand8::Register(A), Operand8::Register(L))),
+        0x7E => Some(Load(Operand8::Register(A), Operand8::MemoryAddress(HL))),
+        0x7F => Some(Load(Operand8::Register(A), Operand8::Register(A))),
         0x80 => Some(Add(Operand8::Register(A), Operand8::Register(B))),
         0x81 => Some(Add(Operand8::Register(A), Operand8::Register(C))),
         0x82 => Some(Add(Operand8::Register(A), Operand8::Register(D))),
@@ -240,6 +490,41 @@ pub fn decode(bytes: &[u8], offset: usize) -> Option<Instruction> {
         0x85 => Some(Add(Operand8::Register(A), Operand8::Register(L))),
         0x86 => Some(Add(Operand8::Register(A), Operand8::MemoryAddress(HL))),
         0x87 => Some(Add(Operand8::Register(A), Operand8::Register(A))),
+        0x88 => Some(AddWithCarry(Operand8::Register(A), Operand8::Register(B))),
+        0x89 => Some(AddWithCarry(Operand8::Register(A), Operand8::Register(C))),
+        0x8A => Some(AddWithCarry(Operand8::Register(A), Operand8::Register(D))),
+        0x8B => Some(AddWithCarry(Operand8::Register(A), Operand8::Register(E))),
+        0x8C => Some(AddWithCarry(Operand8::Register(A), Operand8::Register(H))),
+        0x8D => Some(AddWithCarry(Operand8::Register(A), Operand8::Register(L))),
+        0x8E => Some(AddWithCarry(Operand8::Register(A), Operand8::MemoryAddress(HL))),
+        0x8F => Some(AddWithCarry(Operand8::Register(A), Operand8::Register(A))),
+        0x90 => Some(Subtract(Operand8::Register(B))),
+        0x91 => Some(Subtract(Operand8::Register(C))),
+        0x92 => Some(Subtract(Operand8::Register(D))),
+        0x93 => Some(Subtract(Operand8::Register(E))),
+        0x94 => Some(Subtract(Operand8::Register(H))),
+        0x95 => Some(Subtract(Operand8::Register(L))),
+        0x96 => Some(Subtract(Operand8::MemoryAddress(HL))),
+        0x97 => Some(Subtract(Operand8::Register(A))),
+        0x98 => Some(SubtractWithCarry(Operand8::Register(A), Operand8::Register(B))),
+        0x99 => Some(SubtractWithCarry(Operand8::Register(A), Operand8::Register(C))),
+        0x9A => Some(SubtractWithCarry(Operand8::Register(A), Operand8::Register(D))),
+        0x9B => Some(SubtractWithCarry(Operand8::Register(A), Operand8::Register(E))),
+        0x9C => Some(SubtractWithCarry(Operand8::Register(A), Operand8::Register(H))),
+        0x9D => Some(SubtractWithCarry(Operand8::Register(A), Operand8::Register(L))),
+        0x9E => {
+            Some(SubtractWithCarry(Operand8::Register(A),
+                                   Operand8::MemoryAddress(HL)))
+        }
+        0x9F => Some(SubtractWithCarry(Operand8::Register(A), Operand8::Register(A))),
+        0xA0 => Some(And(Operand8::Register(B))),
+        0xA1 => Some(And(Operand8::Register(C))),
+        0xA2 => Some(And(Operand8::Register(D))),
+        0xA3 => Some(And(Operand8::Register(E))),
+        0xA4 => Some(And(Operand8::Register(H))),
+        0xA5 => Some(And(Operand8::Register(L))),
+        0xA6 => Some(And(Operand8::MemoryAddress(HL))),
+        0xA7 => Some(And(Operand8::Register(A))),
         0xA8 => Some(Xor(Operand8::Register(B))),
         0xA9 => Some(Xor(Operand8::Register(C))),
         0xAA => Some(Xor(Operand8::Register(D))),
@@ -256,49 +541,118 @@ pub fn decode(bytes: &[u8], offset: usize) -> Option<Instruction> {
         0xB5 => Some(Or(Operand8::Register(L))),
         0xB6 => Some(Or(Operand8::MemoryAddress(HL))),
         0xB7 => Some(Or(Operand8::Register(A))),
-        // 0xCB is the prefix for two byte instructions.
+        0xB8 => Some(Compare(Operand8::Register(B))),
+        0xB9 => Some(Compare(Operand8::Register(C))),
+        0xBA => Some(Compare(Operand8::Register(D))),
+        0xBB => Some(Compare(Operand8::Register(E))),
+        0xBC => Some(Compare(Operand8::Register(H))),
+        0xBD => Some(Compare(Operand8::Register(L))),
+        0xBE => Some(Compare(Operand8::MemoryAddress(HL))),
+        0xBF => Some(Compare(Operand8::Register(A))),
+        0xC0 => Some(Return(Condition::NonZero)),
+        0xC1 => Some(Pop(BC)),
+        0xC2 => Some(Jump(Condition::NonZero, decode_immediate16(&bytes[offset + 1..]))),
+        0xC3 => Some(Jump(Condition::Always, decode_immediate16(&bytes[offset + 1..]))),
+        0xC4 => Some(Call(Condition::NonZero, decode_u16(&bytes[offset + 1..]))),
+        0xC5 => Some(Push(BC)),
+        0xC6 => Some(Add(Operand8::Register(A), decode_immediate8(&bytes[offset + 1..]))),
+        0xC7 => Some(Restart(0x00)),
+        0xC8 => Some(Return(Condition::Zero)),
+        0xC9 => Some(Return(Condition::Always)),
+        0xCA => Some(Jump(Condition::Zero, decode_immediate16(&bytes[offset + 1..]))),
+        // 0xCB is the prefix for two byte instructions. The low three
+        // bits select the operand, and for BIT/RES/SET the next three
+        // bits select the bit.
         0xCB => {
-            match bytes[offset + 1] {
-                0x00 => Some(RotateLeftWithCarry(Operand8::Register(B))),
-                0x01 => Some(RotateLeftWithCarry(Operand8::Register(C))),
-                0x02 => Some(RotateLeftWithCarry(Operand8::Register(D))),
-                0x03 => Some(RotateLeftWithCarry(Operand8::Register(E))),
-                0x04 => Some(RotateLeftWithCarry(Operand8::Register(H))),
-                0x05 => Some(RotateLeftWithCarry(Operand8::Register(L))),
-                0x06 => Some(RotateLeftWithCarry(Operand8::MemoryAddress(HL))),
-                0x07 => Some(RotateLeftWithCarry(Operand8::Register(A))),
-                0x08 => Some(RotateRightWithCarry(Operand8::Register(B))),
-                0x09 => Some(RotateRightWithCarry(Operand8::Register(C))),
-                0x0A => Some(RotateRightWithCarry(Operand8::Register(D))),
-                0x0B => Some(RotateRightWithCarry(Operand8::Register(E))),
-                0x0C => Some(RotateRightWithCarry(Operand8::Register(H))),
-                0x0D => Some(RotateRightWithCarry(Operand8::Register(L))),
-                0x0E => Some(RotateRightWithCarry(Operand8::MemoryAddress(HL))),
-                0x0F => Some(RotateRightWithCarry(Operand8::Register(A))),
-                0x40 => Some(Bit(0, Operand8::Register(B))),
-                0x41 => Some(Bit(0, Operand8::Register(C))),
-                0x42 => Some(Bit(0, Operand8::Register(D))),
-                0x43 => Some(Bit(0, Operand8::Register(E))),
-                0x44 => Some(Bit(0, Operand8::Register(H))),
-                0x45 => Some(Bit(0, Operand8::Register(L))),
-                0x46 => Some(Bit(0, Operand8::MemoryAddress(HL))),
-                0x50 => Some(Bit(2, Operand8::Register(B))),
-                0x51 => Some(Bit(2, Operand8::Register(C))),
-                0x52 => Some(Bit(2, Operand8::Register(D))),
-                0x53 => Some(Bit(2, Operand8::Register(E))),
-                0x54 => Some(Bit(2, Operand8::Register(H))),
-                0x55 => Some(Bit(2, Operand8::Register(L))),
-                0x56 => Some(Bit(2, Operand8::MemoryAddress(HL))),
-                0x7C => Some(Bit(7, Operand8::Register(H))),
-                _ => None,
+            let byte2 = bytes[offset + 1];
+            let operand = decode_register_operand(byte2);
+            let bit = (byte2 >> 3) & 0x7;
+
+            match byte2 {
+                0x00..=0x07 => Some(RotateLeftWithCarry(operand)),
+                0x08..=0x0F => Some(RotateRightWithCarry(operand)),
+                0x10..=0x17 => Some(RotateLeft(operand)),
+                0x18..=0x1F => Some(RotateRight(operand)),
+                0x20..=0x27 => Some(ShiftLeftArithmetic(operand)),
+                0x28..=0x2F => Some(ShiftRightArithmetic(operand)),
+                0x30..=0x37 => Some(Swap(operand)),
+                0x38..=0x3F => Some(ShiftRightLogical(operand)),
+                0x40..=0x7F => Some(Bit(bit, operand)),
+                0x80..=0xBF => Some(Reset(bit, operand)),
+                _ => Some(Set(bit, operand)),
             }
         }
+        0xCC => Some(Call(Condition::Zero, decode_u16(&bytes[offset + 1..]))),
+        0xCD => Some(Call(Condition::Always, decode_u16(&bytes[offset + 1..]))),
+        0xCE => {
+            Some(AddWithCarry(Operand8::Register(A),
+                              decode_immediate8(&bytes[offset + 1..])))
+        }
+        0xCF => Some(Restart(0x08)),
+        0xD0 => Some(Return(Condition::NoCarry)),
+        0xD1 => Some(Pop(DE)),
+        0xD2 => Some(Jump(Condition::NoCarry, decode_immediate16(&bytes[offset + 1..]))),
+        0xD4 => Some(Call(Condition::NoCarry, decode_u16(&bytes[offset + 1..]))),
+        0xD5 => Some(Push(DE)),
+        0xD6 => Some(Subtract(decode_immediate8(&bytes[offset + 1..]))),
+        0xD7 => Some(Restart(0x10)),
+        0xD8 => Some(Return(Condition::Carry)),
+        0xD9 => Some(ReturnFromInterrupt),
+        0xDA => Some(Jump(Condition::Carry, decode_immediate16(&bytes[offset + 1..]))),
+        0xDC => Some(Call(Condition::Carry, decode_u16(&bytes[offset + 1..]))),
+        0xDE => {
+            Some(SubtractWithCarry(Operand8::Register(A),
+                                   decode_immediate8(&bytes[offset + 1..])))
+        }
+        0xDF => Some(Restart(0x18)),
+        0xE0 => {
+            Some(Load(Operand8::ConstantMemoryAddressWithOffset(bytes[offset + 1], 0xFF00),
+                      Operand8::Register(A)))
+        }
+        0xE1 => Some(Pop(HL)),
         0xE2 => {
             Some(Load(Operand8::MemoryAddressWithOffset(C, 0xFF00),
                       Operand8::Register(A)))
         }
-        0xEE => Some(Xor(Operand8::Immediate(bytes[offset + 1]))),
-        0xF6 => Some(Or(Operand8::Immediate(bytes[offset + 1]))),
+        0xE5 => Some(Push(HL)),
+        0xE6 => Some(And(decode_immediate8(&bytes[offset + 1..]))),
+        0xE7 => Some(Restart(0x20)),
+        0xE8 => {
+            Some(Add16(Operand16::Register(SP),
+                       Operand16::SignedImmediate(bytes[offset + 1] as i8)))
+        }
+        0xE9 => Some(Jump(Condition::Always, Operand16::Register(HL))),
+        0xEA => {
+            Some(Load(Operand8::ConstantMemoryAddress(decode_u16(&bytes[offset + 1..])),
+                      Operand8::Register(A)))
+        }
+        0xEE => Some(Xor(decode_immediate8(&bytes[offset + 1..]))),
+        0xEF => Some(Restart(0x28)),
+        0xF0 => {
+            Some(Load(Operand8::Register(A),
+                      Operand8::ConstantMemoryAddressWithOffset(bytes[offset + 1], 0xFF00)))
+        }
+        0xF1 => Some(Pop(AF)),
+        0xF2 => {
+            Some(Load(Operand8::Register(A),
+                      Operand8::MemoryAddressWithOffset(C, 0xFF00)))
+        }
+        0xF3 => Some(DisableInterrupts),
+        0xF5 => Some(Push(AF)),
+        0xF6 => Some(Or(decode_immediate8(&bytes[offset + 1..]))),
+        0xF7 => Some(Restart(0x30)),
+        0xF8 => {
+            Some(Load16(Operand16::Register(HL),
+                        Operand16::StackPointerWithOffset(bytes[offset + 1] as i8)))
+        }
+        0xF9 => Some(Load16(Operand16::Register(SP), Operand16::Register(HL))),
+        0xFA => {
+            Some(Load(Operand8::Register(A),
+                      Operand8::ConstantMemoryAddress(decode_u16(&bytes[offset + 1..]))))
+        }
+        0xFB => Some(EnableInterrupts),
+        0xFE => Some(Compare(decode_immediate8(&bytes[offset + 1..]))),
+        0xFF => Some(Restart(0x38)),
         _ => None,
     }
 }
@@ -307,53 +661,172 @@ pub fn decode(bytes: &[u8], offset: usize) -> Option<Instruction> {
 pub fn instr_size(instr: &Instruction) -> usize {
     match *instr {
         Nop => 1,
-        Stop => 1,
+        // STOP is followed by a padding byte, usually 0x00.
+        Stop => 2,
         Halt => 1,
-        Add(_, _) => 1,
+        DisableInterrupts => 1,
+        EnableInterrupts => 1,
+        Add(_, ref src) => 1 + operand8_size(src),
+        AddWithCarry(_, ref src) => 1 + operand8_size(src),
+        Add16(_, Operand16::SignedImmediate(_)) => 2,
         Add16(_, _) => 1,
-        Or(Operand8::Immediate(_)) => 2,
-        Or(_) => 1,
-        Xor(Operand8::Immediate(_)) => 2,
-        Xor(_) => 1,
+        Subtract(ref src) => 1 + operand8_size(src),
+        SubtractWithCarry(_, ref src) => 1 + operand8_size(src),
+        And(ref src) => 1 + operand8_size(src),
+        Or(ref src) => 1 + operand8_size(src),
+        Xor(ref src) => 1 + operand8_size(src),
+        Compare(ref src) => 1 + operand8_size(src),
+        DecimalAdjust => 1,
+        Complement => 1,
+        SetCarryFlag => 1,
+        ComplementCarryFlag => 1,
         Increment(_) => 1,
         Increment16(_) => 1,
         Decrement(_) => 1,
         Decrement16(_) => 1,
-        RotateLeftWithCarry(_) => 1,
-        RotateRightWithCarry(_) => 1,
-        Load(_, ref src) => {
-            match *src {
-                Operand8::Immediate(_) => 2,
-                _ => 1,
-            }
-        }
-        Load16(_, ref src) => {
-            match *src {
-                Operand16::Immediate(_) => 3,
-                _ => 1,
-            }
-        }
+        RotateLeftWithCarry(_) => 2,
+        RotateRightWithCarry(_) => 2,
+        RotateLeft(_) => 2,
+        RotateRight(_) => 2,
+        ShiftLeftArithmetic(_) => 2,
+        ShiftRightArithmetic(_) => 2,
+        ShiftRightLogical(_) => 2,
+        Swap(_) => 2,
+        RotateLeftWithCarryA => 1,
+        RotateRightWithCarryA => 1,
+        RotateLeftA => 1,
+        RotateRightA => 1,
+        Load(ref dest, ref src) => 1 + operand8_size(dest) + operand8_size(src),
+        Load16(_, Operand16::Immediate(_)) => 3,
+        Load16(Operand16::ConstantMemoryAddress(_), _) => 3,
+        Load16(_, Operand16::StackPointerWithOffset(_)) => 2,
+        Load16(_, _) => 1,
         LoadIncrement(_, _) => 1,
         LoadDecrement(_, _) => 1,
         Bit(_, _) => 2,
+        Reset(_, _) => 2,
+        Set(_, _) => 2,
         JumpRelative(_, _) => 2,
+        Jump(_, Operand16::Immediate(_)) => 3,
+        Jump(_, _) => 1,
+        Call(_, _) => 3,
+        Return(_) => 1,
+        ReturnFromInterrupt => 1,
+        Restart(_) => 1,
+        Push(_) => 1,
+        Pop(_) => 1,
     }
 }
 
-/// Decode little-endian bytes as a 16-bit integer.
-fn decode_immediate16(bytes: &[u8]) -> Operand16 {
+/// The number of bytes an 8-bit operand occupies after the opcode.
+fn operand8_size(operand: &Operand8) -> usize {
+    match *operand {
+        Operand8::Immediate(_) => 1,
+        Operand8::ConstantMemoryAddressWithOffset(_, _) => 1,
+        Operand8::ConstantMemoryAddress(_) => 2,
+        _ => 0,
+    }
+}
+
+/// Given an instruction, return the number of M-cycles it takes to
+/// execute. Conditional jumps, calls and returns take longer when the
+/// branch is taken.
+pub fn instr_cycles(instr: &Instruction, branch_taken: bool) -> u32 {
+    match *instr {
+        Nop => 1,
+        Stop => 1,
+        Halt => 1,
+        DisableInterrupts => 1,
+        EnableInterrupts => 1,
+        Add(_, ref src) => 1 + operand8_cycles(src),
+        AddWithCarry(_, ref src) => 1 + operand8_cycles(src),
+        Add16(_, Operand16::SignedImmediate(_)) => 4,
+        Add16(_, _) => 2,
+        Subtract(ref src) => 1 + operand8_cycles(src),
+        SubtractWithCarry(_, ref src) => 1 + operand8_cycles(src),
+        And(ref src) => 1 + operand8_cycles(src),
+        Or(ref src) => 1 + operand8_cycles(src),
+        Xor(ref src) => 1 + operand8_cycles(src),
+        Compare(ref src) => 1 + operand8_cycles(src),
+        DecimalAdjust => 1,
+        Complement => 1,
+        SetCarryFlag => 1,
+        ComplementCarryFlag => 1,
+        // Read-modify-write instructions access (HL) twice.
+        Increment(ref target) => 1 + 2 * operand8_cycles(target),
+        Decrement(ref target) => 1 + 2 * operand8_cycles(target),
+        Increment16(_) => 2,
+        Decrement16(_) => 2,
+        RotateLeftWithCarry(ref target) => 2 + 2 * operand8_cycles(target),
+        RotateRightWithCarry(ref target) => 2 + 2 * operand8_cycles(target),
+        RotateLeft(ref target) => 2 + 2 * operand8_cycles(target),
+        RotateRight(ref target) => 2 + 2 * operand8_cycles(target),
+        ShiftLeftArithmetic(ref target) => 2 + 2 * operand8_cycles(target),
+        ShiftRightArithmetic(ref target) => 2 + 2 * operand8_cycles(target),
+        ShiftRightLogical(ref target) => 2 + 2 * operand8_cycles(target),
+        Swap(ref target) => 2 + 2 * operand8_cycles(target),
+        RotateLeftWithCarryA => 1,
+        RotateRightWithCarryA => 1,
+        RotateLeftA => 1,
+        RotateRightA => 1,
+        Load(ref dest, ref src) => 1 + operand8_cycles(dest) + operand8_cycles(src),
+        Load16(_, Operand16::Immediate(_)) => 3,
+        Load16(Operand16::ConstantMemoryAddress(_), _) => 5,
+        Load16(_, Operand16::StackPointerWithOffset(_)) => 3,
+        Load16(_, _) => 2,
+        LoadIncrement(_, _) => 2,
+        LoadDecrement(_, _) => 2,
+        // BIT only reads its operand.
+        Bit(_, ref target) => 2 + operand8_cycles(target),
+        Reset(_, ref target) => 2 + 2 * operand8_cycles(target),
+        Set(_, ref target) => 2 + 2 * operand8_cycles(target),
+        JumpRelative(Condition::Always, _) => 3,
+        JumpRelative(_, _) => if branch_taken { 3 } else { 2 },
+        Jump(Condition::Always, Operand16::Immediate(_)) => 4,
+        Jump(_, Operand16::Immediate(_)) => if branch_taken { 4 } else { 3 },
+        Jump(_, _) => 1,
+        Call(Condition::Always, _) => 6,
+        Call(_, _) => if branch_taken { 6 } else { 3 },
+        Return(Condition::Always) => 4,
+        // Conditional returns spend an extra cycle checking the
+        // condition.
+        Return(_) => if branch_taken { 5 } else { 2 },
+        ReturnFromInterrupt => 4,
+        Restart(_) => 4,
+        Push(_) => 4,
+        Pop(_) => 3,
+    }
+}
+
+/// The number of extra M-cycles needed to fetch an 8-bit operand or
+/// access the memory it refers to.
+fn operand8_cycles(operand: &Operand8) -> u32 {
+    match *operand {
+        Operand8::Register(_) => 0,
+        Operand8::Immediate(_) => 1,
+        Operand8::MemoryAddress(_) => 1,
+        Operand8::MemoryAddressWithOffset(_, _) => 1,
+        Operand8::ConstantMemoryAddressWithOffset(_, _) => 2,
+        Operand8::ConstantMemoryAddress(_) => 3,
+    }
+}
+
+/// Decode little-endian bytes as an unsigned 16-bit integer.
+fn decode_u16(bytes: &[u8]) -> u16 {
     let low_byte = bytes[0] as u16;
     let high_byte = bytes[1] as u16;
 
-    Operand16::Immediate((high_byte << 8) + low_byte)
+    (high_byte << 8) + low_byte
+}
+
+/// Decode little-endian bytes as a 16-bit integer.
+fn decode_immediate16(bytes: &[u8]) -> Operand16 {
+    Operand16::Immediate(decode_u16(bytes))
 }
 
 /// Decode little-endian bytes as a 16-bit memory address.
 fn decode_constant_address(bytes: &[u8]) -> Operand16 {
-    let low_byte = bytes[0] as u16;
-    let high_byte = bytes[1] as u16;
-
-    Operand16::ConstantMemoryAddress((high_byte << 8) + low_byte)
+    Operand16::ConstantMemoryAddress(decode_u16(bytes))
 }
 
 /// Decode byte as an 8-bit integer.
@@ -361,72 +834,714 @@ fn decode_immediate8(bytes: &[u8]) -> Operand8 {
     Operand8::Immediate(bytes[0])
 }
 
-pub fn step(cpu: &mut CPU, i: Instruction) -> Result<(), String> {
-    println!("{:?}", cpu);
-    println!("Executing: {:?}", i);
+/// Decode the operand encoded in the low three bits of an opcode.
+fn decode_register_operand(byte: u8) -> Operand8 {
+    match byte & 0x7 {
+        0 => Operand8::Register(B),
+        1 => Operand8::Register(C),
+        2 => Operand8::Register(D),
+        3 => Operand8::Register(E),
+        4 => Operand8::Register(H),
+        5 => Operand8::Register(L),
+        6 => Operand8::MemoryAddress(HL),
+        _ => Operand8::Register(A),
+    }
+}
+
+// Combine two 8-bit values into a 16-bit value.
+fn register_pair(high: u8, low: u8) -> u16 {
+    ((high as u16) << 8) + low as u16
+}
+
+/// Read a 16-bit register, or a pair of 8-bit registers as a single
+/// 16-bit value.
+fn read_register16(cpu: &CPU, register: Register16) -> u16 {
+    match register {
+        BC => register_pair(cpu.b.0, cpu.c.0),
+        DE => register_pair(cpu.d.0, cpu.e.0),
+        HL => register_pair(cpu.h.0, cpu.l.0),
+        AF => register_pair(cpu.a.0, cpu.flags.to_byte()),
+        SP => cpu.sp.0,
+    }
+}
+
+/// Write a 16-bit value to a 16-bit register or register pair. When
+/// writing AF, the lower nibble of F is discarded.
+fn write_register16(cpu: &mut CPU, register: Register16, value: u16) {
+    let high = Wrapping((value >> 8) as u8);
+    let low = Wrapping(value as u8);
+
+    match register {
+        BC => {
+            cpu.b = high;
+            cpu.c = low;
+        }
+        DE => {
+            cpu.d = high;
+            cpu.e = low;
+        }
+        HL => {
+            cpu.h = high;
+            cpu.l = low;
+        }
+        AF => {
+            cpu.a = high;
+            cpu.flags = Flags::from_byte(low.0);
+        }
+        SP => cpu.sp = Wrapping(value),
+    }
+}
+
+/// The address in memory that an 8-bit operand refers to.
+fn operand_address(cpu: &mut CPU, operand: Operand8) -> u16 {
+    match operand {
+        Operand8::MemoryAddress(register) => read_register16(cpu, register),
+        Operand8::MemoryAddressWithOffset(register, offset) => {
+            offset.wrapping_add(register8(cpu, register).0 as u16)
+        }
+        Operand8::ConstantMemoryAddress(address) => address,
+        Operand8::ConstantMemoryAddressWithOffset(value, offset) => {
+            offset.wrapping_add(value as u16)
+        }
+        _ => panic!("{:?} does not refer to memory", operand),
+    }
+}
+
+/// Read the current value of an 8-bit operand.
+fn read_operand8(cpu: &mut CPU, operand: Operand8) -> u8 {
+    match operand {
+        Operand8::Register(register) => register8(cpu, register).0,
+        Operand8::Immediate(value) => value,
+        _ => {
+            let address = operand_address(cpu, operand);
+            cpu.memory.read(address)
+        }
+    }
+}
+
+/// Write a value to the register or memory location of an 8-bit operand.
+fn write_operand8(cpu: &mut CPU, operand: Operand8, value: u8) {
+    match operand {
+        Operand8::Register(register) => {
+            *register8(cpu, register) = Wrapping(value);
+        }
+        Operand8::Immediate(_) => panic!("Cannot write to {:?}", operand),
+        _ => {
+            let address = operand_address(cpu, operand);
+            cpu.memory.write(address, value);
+        }
+    }
+}
+
+/// Add a signed offset to SP, setting flags, and return the result.
+/// Shared by ADD SP,e8 and LD HL,SP+e8, which set H and C based on
+/// the low byte as if it were an unsigned 8-bit addition.
+fn add_sp_offset(cpu: &mut CPU, offset: i8) -> u16 {
+    let sp = cpu.sp.0;
+    let value = offset as u8 as u16;
+
+    let half_carry = (sp & 0xF) + (value & 0xF) > 0xF;
+    let carry = (sp & 0xFF) + value > 0xFF;
+    cpu.flags = Flags::new(false, false, half_carry, carry);
+
+    sp.wrapping_add(offset as u16)
+}
+
+/// Would this instruction branch, given the current flags?
+fn branch_taken(cpu: &CPU, instr: &Instruction) -> bool {
+    match *instr {
+        JumpRelative(condition, _) => condition_holds(cpu, condition),
+        Jump(condition, _) => condition_holds(cpu, condition),
+        Call(condition, _) => condition_holds(cpu, condition),
+        Return(condition) => condition_holds(cpu, condition),
+        _ => false,
+    }
+}
+
+fn condition_holds(cpu: &CPU, condition: Condition) -> bool {
+    match condition {
+        Condition::Always => true,
+        Condition::Zero => cpu.flags.zero(),
+        Condition::NonZero => !cpu.flags.zero(),
+        Condition::Carry => cpu.flags.carry(),
+        Condition::NoCarry => !cpu.flags.carry(),
+    }
+}
+
+/// Push a 16-bit value onto the stack, high byte first.
+fn push16(cpu: &mut CPU, value: u16) {
+    cpu.sp -= Wrapping(1);
+    cpu.memory.write(cpu.sp.0, (value >> 8) as u8);
+    cpu.sp -= Wrapping(1);
+    cpu.memory.write(cpu.sp.0, value as u8);
+}
+
+/// Pop a 16-bit value off the stack.
+fn pop16(cpu: &mut CPU) -> u16 {
+    let low = cpu.memory.read(cpu.sp.0);
+    cpu.sp += Wrapping(1);
+    let high = cpu.memory.read(cpu.sp.0);
+    cpu.sp += Wrapping(1);
+    register_pair(high, low)
+}
+
+/// Add `value` (and optionally the carry) to A, set flags, and return
+/// the result.
+fn add8(cpu: &mut CPU, value: u8, carry: bool) -> u8 {
+    let a = cpu.a.0;
+    let carry = carry as u8;
+    let result = a.wrapping_add(value).wrapping_add(carry);
+
+    let half_carry = (a & 0xF) + (value & 0xF) + carry > 0xF;
+    let full_carry = a as u16 + value as u16 + carry as u16 > 0xFF;
+    cpu.flags = Flags::new(result == 0, false, half_carry, full_carry);
+
+    result
+}
+
+/// Subtract `value` (and optionally the carry) from A, set flags, and
+/// return the result.
+fn subtract8(cpu: &mut CPU, value: u8, carry: bool) -> u8 {
+    let a = cpu.a.0;
+    let carry = carry as u8;
+    let result = a.wrapping_sub(value).wrapping_sub(carry);
+
+    let half_carry = (a & 0xF) < (value & 0xF) + carry;
+    let full_carry = (a as u16) < value as u16 + carry as u16;
+    cpu.flags = Flags::new(result == 0, true, half_carry, full_carry);
+
+    result
+}
+
+/// Execute a single instruction, returning the number of M-cycles it
+/// took.
+pub fn step(cpu: &mut CPU, i: Instruction) -> Result<u32, String> {
+    if cpu.trace {
+        println!("{:?}", cpu);
+        println!("Executing: {:?}", i);
+    }
     
-    cpu.pc = cpu.pc + Wrapping(1);
-    cpu.m = Wrapping(1);
-    cpu.t = Wrapping(4);
+    // PC points at the next instruction while this one executes, so
+    // relative jumps and return addresses are based on it.
+    cpu.pc += Wrapping(instr_size(&i) as u16);
+    let taken = branch_taken(cpu, &i);
+    let cycles = instr_cycles(&i, taken);
 
     match i {
         Nop => {}
-        Xor(Operand8::Register(register_name)) => {
-            let register_value = *register8(cpu, register_name);
-            cpu.a = cpu.a ^ register_value;
+        Stop => {
+            cpu.stopped = true;
+            // STOP resets the divider.
+            cpu.memory.write(DIV_ADDRESS, 0);
         }
-        Increment(Operand8::Register(target)) => {
-            // TODO: flags
-            let mut reg = register8(cpu, target);
-            *reg = *reg + Wrapping(1);
+        Halt => {
+            if !cpu.ime && pending_interrupts(cpu) != 0 {
+                cpu.halt_bug = true;
+            } else {
+                cpu.halted = true;
+            }
         }
-        Load16(Operand16::Register(target), Operand16::Immediate(value)) => {
-            let upper_bits = (value >> 8) as u8;
-            let lower_bits = (value & 0xF) as u8;
-
-            match target {
-                Register16::SP => {
-                    cpu.sp = Wrapping::<u16>(value);
+        DisableInterrupts => {
+            cpu.ime = false;
+            cpu.ime_scheduled = false;
+        }
+        EnableInterrupts => {
+            cpu.ime_scheduled = true;
+        }
+        JumpRelative(_, offset) => {
+            if taken {
+                cpu.pc += Wrapping(offset as u16);
+            }
+        }
+        Jump(Condition::Always, Operand16::Register(HL)) => {
+            cpu.pc = Wrapping(read_register16(cpu, HL));
+        }
+        Jump(_, Operand16::Immediate(address)) => {
+            if taken {
+                cpu.pc = Wrapping(address);
+            }
+        }
+        Call(_, address) => {
+            if taken {
+                let return_address = cpu.pc.0;
+                push16(cpu, return_address);
+                cpu.pc = Wrapping(address);
+            }
+        }
+        Return(_) => {
+            if taken {
+                cpu.pc = Wrapping(pop16(cpu));
+            }
+        }
+        ReturnFromInterrupt => {
+            // Unlike EI, RETI enables interrupts immediately.
+            cpu.pc = Wrapping(pop16(cpu));
+            cpu.ime = true;
+        }
+        Restart(address) => {
+            let return_address = cpu.pc.0;
+            push16(cpu, return_address);
+            cpu.pc = Wrapping(address as u16);
+        }
+        Add(_, src) => {
+            let value = read_operand8(cpu, src);
+            cpu.a = Wrapping(add8(cpu, value, false));
+        }
+        AddWithCarry(_, src) => {
+            let value = read_operand8(cpu, src);
+            let carry = cpu.flags.carry();
+            cpu.a = Wrapping(add8(cpu, value, carry));
+        }
+        Subtract(src) => {
+            let value = read_operand8(cpu, src);
+            cpu.a = Wrapping(subtract8(cpu, value, false));
+        }
+        SubtractWithCarry(_, src) => {
+            let value = read_operand8(cpu, src);
+            let carry = cpu.flags.carry();
+            cpu.a = Wrapping(subtract8(cpu, value, carry));
+        }
+        Compare(src) => {
+            // CP is a subtraction that only keeps the flags.
+            let value = read_operand8(cpu, src);
+            subtract8(cpu, value, false);
+        }
+        And(src) => {
+            let value = read_operand8(cpu, src);
+            cpu.a &= Wrapping(value);
+            cpu.flags = Flags::new(cpu.a.0 == 0, false, true, false);
+        }
+        Or(src) => {
+            let value = read_operand8(cpu, src);
+            cpu.a |= Wrapping(value);
+            cpu.flags = Flags::new(cpu.a.0 == 0, false, false, false);
+        }
+        Xor(src) => {
+            let value = read_operand8(cpu, src);
+            cpu.a ^= Wrapping(value);
+            cpu.flags = Flags::new(cpu.a.0 == 0, false, false, false);
+        }
+        DecimalAdjust => {
+            // Correct A after a BCD addition or subtraction, using the
+            // flags left by that operation to work out which digits
+            // overflowed.
+            let mut a = cpu.a.0;
+            let mut carry = cpu.flags.carry();
+            if !cpu.flags.subtract() {
+                if carry || a > 0x99 {
+                    a = a.wrapping_add(0x60);
+                    carry = true;
+                }
+                if cpu.flags.half_carry() || a & 0xF > 0x9 {
+                    a = a.wrapping_add(0x06);
                 }
-                Register16::HL => {
-                    cpu.h = Wrapping::<u8>(upper_bits);
-                    cpu.l = Wrapping::<u8>(lower_bits);
+            } else {
+                if carry {
+                    a = a.wrapping_sub(0x60);
+                }
+                if cpu.flags.half_carry() {
+                    a = a.wrapping_sub(0x06);
                 }
-                _ => unimplemented!()
             }
-            
+
+            cpu.a = Wrapping(a);
+            cpu.flags.set_zero(a == 0);
+            cpu.flags.set_half_carry(false);
+            cpu.flags.set_carry(carry);
+        }
+        Complement => {
+            cpu.a = !cpu.a;
+            cpu.flags.set_subtract(true);
+            cpu.flags.set_half_carry(true);
+        }
+        SetCarryFlag => {
+            cpu.flags.set_subtract(false);
+            cpu.flags.set_half_carry(false);
+            cpu.flags.set_carry(true);
+        }
+        ComplementCarryFlag => {
+            let carry = cpu.flags.carry();
+            cpu.flags.set_subtract(false);
+            cpu.flags.set_half_carry(false);
+            cpu.flags.set_carry(!carry);
+        }
+        Increment(target) => {
+            // INC leaves the carry flag untouched.
+            let value = read_operand8(cpu, target);
+            let result = value.wrapping_add(1);
+            write_operand8(cpu, target, result);
+            cpu.flags.set_zero(result == 0);
+            cpu.flags.set_subtract(false);
+            cpu.flags.set_half_carry(value & 0xF == 0xF);
+        }
+        Decrement(target) => {
+            // DEC leaves the carry flag untouched.
+            let value = read_operand8(cpu, target);
+            let result = value.wrapping_sub(1);
+            write_operand8(cpu, target, result);
+            cpu.flags.set_zero(result == 0);
+            cpu.flags.set_subtract(true);
+            cpu.flags.set_half_carry(value & 0xF == 0);
+        }
+        RotateLeftWithCarryA => {
+            let value = cpu.a.0;
+            cpu.a = Wrapping(value.rotate_left(1));
+            cpu.flags = Flags::new(false, false, false, value & 0x80 != 0);
+        }
+        RotateRightWithCarryA => {
+            let value = cpu.a.0;
+            cpu.a = Wrapping(value.rotate_right(1));
+            cpu.flags = Flags::new(false, false, false, value & 0x01 != 0);
+        }
+        RotateLeftA => {
+            let value = cpu.a.0;
+            cpu.a = Wrapping((value << 1) | cpu.flags.carry() as u8);
+            cpu.flags = Flags::new(false, false, false, value & 0x80 != 0);
+        }
+        RotateRightA => {
+            let value = cpu.a.0;
+            cpu.a = Wrapping((value >> 1) | ((cpu.flags.carry() as u8) << 7));
+            cpu.flags = Flags::new(false, false, false, value & 0x01 != 0);
+        }
+        RotateLeftWithCarry(target) => {
+            let value = read_operand8(cpu, target);
+            let result = value.rotate_left(1);
+            write_operand8(cpu, target, result);
+            cpu.flags = Flags::new(result == 0, false, false, value & 0x80 != 0);
+        }
+        RotateRightWithCarry(target) => {
+            let value = read_operand8(cpu, target);
+            let result = value.rotate_right(1);
+            write_operand8(cpu, target, result);
+            cpu.flags = Flags::new(result == 0, false, false, value & 0x01 != 0);
+        }
+        RotateLeft(target) => {
+            let value = read_operand8(cpu, target);
+            let result = (value << 1) | cpu.flags.carry() as u8;
+            write_operand8(cpu, target, result);
+            cpu.flags = Flags::new(result == 0, false, false, value & 0x80 != 0);
+        }
+        RotateRight(target) => {
+            let value = read_operand8(cpu, target);
+            let result = (value >> 1) | ((cpu.flags.carry() as u8) << 7);
+            write_operand8(cpu, target, result);
+            cpu.flags = Flags::new(result == 0, false, false, value & 0x01 != 0);
+        }
+        ShiftLeftArithmetic(target) => {
+            let value = read_operand8(cpu, target);
+            let result = value << 1;
+            write_operand8(cpu, target, result);
+            cpu.flags = Flags::new(result == 0, false, false, value & 0x80 != 0);
+        }
+        ShiftRightArithmetic(target) => {
+            // Bit 7 is preserved, so the sign of the value is kept.
+            let value = read_operand8(cpu, target);
+            let result = (value >> 1) | (value & 0x80);
+            write_operand8(cpu, target, result);
+            cpu.flags = Flags::new(result == 0, false, false, value & 0x01 != 0);
+        }
+        ShiftRightLogical(target) => {
+            let value = read_operand8(cpu, target);
+            let result = value >> 1;
+            write_operand8(cpu, target, result);
+            cpu.flags = Flags::new(result == 0, false, false, value & 0x01 != 0);
+        }
+        Swap(target) => {
+            let value = read_operand8(cpu, target);
+            let result = value.rotate_left(4);
+            write_operand8(cpu, target, result);
+            cpu.flags = Flags::new(result == 0, false, false, false);
+        }
+        Bit(bit, target) => {
+            // BIT leaves the carry flag untouched.
+            let value = read_operand8(cpu, target);
+            cpu.flags.set_zero(value & (1 << bit) == 0);
+            cpu.flags.set_subtract(false);
+            cpu.flags.set_half_carry(true);
+        }
+        Reset(bit, target) => {
+            let value = read_operand8(cpu, target);
+            write_operand8(cpu, target, value & !(1 << bit));
+        }
+        Set(bit, target) => {
+            let value = read_operand8(cpu, target);
+            write_operand8(cpu, target, value | (1 << bit));
+        }
+        Load(dest, src) => {
+            let value = read_operand8(cpu, src);
+            write_operand8(cpu, dest, value);
+        }
+        LoadIncrement(dest, src) => {
+            let value = read_operand8(cpu, src);
+            write_operand8(cpu, dest, value);
+            let hl = read_register16(cpu, HL);
+            write_register16(cpu, HL, hl.wrapping_add(1));
+        }
+        LoadDecrement(dest, src) => {
+            let value = read_operand8(cpu, src);
+            write_operand8(cpu, dest, value);
+            let hl = read_register16(cpu, HL);
+            write_register16(cpu, HL, hl.wrapping_sub(1));
+        }
+        Load16(Operand16::ConstantMemoryAddress(address), Operand16::Register(SP)) => {
+            let sp = cpu.sp.0;
+            cpu.memory.write(address, sp as u8);
+            cpu.memory.write(address.wrapping_add(1), (sp >> 8) as u8);
+        }
+        Load16(Operand16::Register(HL), Operand16::StackPointerWithOffset(offset)) => {
+            let value = add_sp_offset(cpu, offset);
+            write_register16(cpu, HL, value);
+        }
+        Add16(Operand16::Register(SP), Operand16::SignedImmediate(offset)) => {
+            cpu.sp = Wrapping(add_sp_offset(cpu, offset));
+        }
+        Load16(Operand16::Register(target), Operand16::Immediate(value)) => {
+            write_register16(cpu, target, value);
+        }
+        Load16(Operand16::Register(SP), Operand16::Register(HL)) => {
+            cpu.sp = Wrapping(read_register16(cpu, HL));
+        }
+        Increment16(Operand16::Register(target)) => {
+            let value = read_register16(cpu, target);
+            write_register16(cpu, target, value.wrapping_add(1));
+        }
+        Decrement16(Operand16::Register(target)) => {
+            let value = read_register16(cpu, target);
+            write_register16(cpu, target, value.wrapping_sub(1));
+        }
+        Add16(Operand16::Register(HL), Operand16::Register(src)) => {
+            // Z is untouched, H and C are carries out of bits 11 and 15.
+            let hl = read_register16(cpu, HL);
+            let value = read_register16(cpu, src);
+            write_register16(cpu, HL, hl.wrapping_add(value));
+
+            cpu.flags.set_subtract(false);
+            cpu.flags.set_half_carry((hl & 0xFFF) + (value & 0xFFF) > 0xFFF);
+            cpu.flags.set_carry(hl as u32 + value as u32 > 0xFFFF);
+        }
+        Push(register) => {
+            let value = read_register16(cpu, register);
+            push16(cpu, value);
+        }
+        Pop(register) => {
+            let value = pop16(cpu);
+            write_register16(cpu, register, value);
         }
         _ => return Err(format!("Don't know how to execute {:?}", i)),
     }
 
-    Ok(())
+    cpu.cycles += cycles as u64;
+
+    Ok(cycles)
 }
 
-pub fn fetch_execute(bytes: &[u8]) -> Result<(), String> {
-    let mut cpu = initial_cpu();
-    
-    let mut offset = 0;
-    while offset < bytes.len() {
-        let instr = decode(bytes, offset);
-
-        match instr {
-            Some(instr) => {
-                let byte_count = instr_size(&instr);
-                try!(step(&mut cpu, instr));
-                
-                offset += byte_count;
+/// Decode the instruction at PC.
+fn fetch(cpu: &mut CPU) -> Option<Instruction> {
+    let pc = cpu.pc.0;
+    let mut bytes = [cpu.memory.read(pc),
+                     cpu.memory.read(pc.wrapping_add(1)),
+                     cpu.memory.read(pc.wrapping_add(2))];
+
+    if cpu.halt_bug {
+        // PC isn't incremented after the opcode is read, so the
+        // opcode byte is read again as the first operand byte, and
+        // the instruction ends one byte earlier than it should.
+        cpu.halt_bug = false;
+        bytes = [bytes[0], bytes[0], bytes[1]];
+        cpu.pc -= Wrapping(1);
+    }
+
+    decode(&bytes, 0)
+}
+
+/// Interrupts that are both requested (IF) and enabled (IE).
+fn pending_interrupts(cpu: &CPU) -> u8 {
+    cpu.memory.read(IE_ADDRESS) & cpu.memory.read(IF_ADDRESS) & 0x1F
+}
+
+/// If an interrupt is pending and IME is set, jump to its
+/// handler. Returns the number of M-cycles taken, which is zero if no
+/// interrupt was dispatched.
+fn dispatch_interrupt(cpu: &mut CPU) -> u32 {
+    if !cpu.ime {
+        return 0;
+    }
+
+    match highest_priority(pending_interrupts(cpu)) {
+        Some(interrupt) => {
+            let requested = cpu.memory.read(IF_ADDRESS);
+            cpu.memory.write(IF_ADDRESS, requested & !interrupt.bit());
+            cpu.ime = false;
+
+            let return_address = cpu.pc.0;
+            push16(cpu, return_address);
+            cpu.pc = Wrapping(interrupt.vector());
+            5
+        }
+        None => 0,
+    }
+}
+
+/// Service any pending interrupt, then execute the instruction at
+/// PC, and let the rest of the hardware catch up. Returns the number
+/// of M-cycles taken.
+pub fn execute_next(cpu: &mut CPU) -> Result<u32, String> {
+    let cycles = execute(cpu)?;
+    cpu.memory.tick(cycles);
+    Ok(cycles)
+}
+
+fn execute(cpu: &mut CPU) -> Result<u32, String> {
+    if cpu.halted {
+        if pending_interrupts(cpu) == 0 {
+            // Nothing to do until an interrupt is requested.
+            cpu.cycles += 1;
+            return Ok(1);
+        }
+
+        // Any pending interrupt ends HALT, even when IME is clear. Waking
+        // up costs an extra cycle when the interrupt is dispatched.
+        cpu.halted = false;
+        let cycles = dispatch_interrupt(cpu);
+        if cycles > 0 {
+            cpu.cycles += cycles as u64 + 1;
+            return Ok(cycles + 1);
+        }
+    }
+
+    let cycles = dispatch_interrupt(cpu);
+    if cycles > 0 {
+        cpu.cycles += cycles as u64;
+        return Ok(cycles);
+    }
+
+    // If the previous instruction was EI, IME is set once this
+    // instruction has executed (unless it's a DI).
+    let enable_interrupts = cpu.ime_scheduled;
+
+    let cycles = match fetch(cpu) {
+        Some(instr) => step(cpu, instr)?,
+        None => {
+            return Err(
+                format!("Could not decode instruction at {:04X} bytes {:02X}",
+                        cpu.pc.0, cpu.memory.read(cpu.pc.0)));
+        }
+    };
+
+    if enable_interrupts && cpu.ime_scheduled {
+        cpu.ime = true;
+        cpu.ime_scheduled = false;
+    }
+
+    Ok(cycles)
+}
+
+/// Set which buttons are held down, e.g. once per frame. Pressing a
+/// button the game is reading requests the joypad interrupt.
+pub fn set_buttons(cpu: &mut CPU, buttons: Buttons) {
+    cpu.memory.set_buttons(buttons);
+}
+
+/// Make `run` return once the CPU has executed this many M-cycles in
+/// total. Without a limit, it only returns on STOP, on a HALT that can
+/// never wake up, or on an error.
+pub fn set_cycle_limit(cpu: &mut CPU, limit: Option<u64>) {
+    cpu.cycle_limit = limit;
+}
+
+/// Print each instruction as it's executed.
+pub fn set_trace(cpu: &mut CPU, trace: bool) {
+    cpu.trace = trace;
+}
+
+/// Choose where bytes sent over the serial port go. By default
+/// they're kept in a buffer, see `serial_output`.
+pub fn set_serial_output(cpu: &mut CPU, output: SerialOutput) {
+    cpu.memory.set_serial_output(output);
+}
+
+/// Connect the serial port to another Game Boy.
+pub fn set_link(cpu: &mut CPU, link: Box<dyn Link>) {
+    cpu.memory.set_link(link);
+}
+
+/// Bytes sent over the serial port so far.
+pub fn serial_output(cpu: &CPU) -> &[u8] {
+    cpu.memory.serial_output()
+}
+
+/// The last complete frame, as SCREEN_WIDTH x SCREEN_HEIGHT shades
+/// from 0 (white) to 3 (black).
+pub fn frame(cpu: &CPU) -> &[u8] {
+    cpu.memory.frame()
+}
+
+/// How many frames have been completed. This goes up by one at the
+/// start of each VBlank, so a frontend can tell when to redraw.
+pub fn frame_count(cpu: &CPU) -> u64 {
+    cpu.memory.frame_count()
+}
+
+/// How often battery-backed RAM is saved while running, in
+/// M-cycles. This is roughly once a second.
+const SAVE_INTERVAL: u64 = 1 << 20;
+
+/// Load a ROM into memory and execute it, starting from the boot ROM
+/// if there is one, until we reach a STOP or the cycle limit.
+/// Returns the final CPU state.
+///
+/// If a save file is given, cartridge RAM is loaded from it at the
+/// start and written back periodically and at the end.
+pub fn fetch_execute(bytes: &[u8], model: Model, boot_rom: Option<&[u8]>,
+                     save_file: Option<&mut SaveFile>) -> Result<CPU, String> {
+    let mut cpu = load_cartridge(bytes, model, boot_rom)?;
+    run(&mut cpu, save_file)?;
+    Ok(cpu)
+}
+
+/// Execute from the current state until we reach a STOP or the cycle
+/// limit set with `set_cycle_limit`. Saving works as for
+/// `fetch_execute`.
+pub fn run(cpu: &mut CPU, mut save_file: Option<&mut SaveFile>) -> Result<(), String> {
+    if let Some(ref mut save_file) = save_file {
+        save_file.load(&mut cpu.memory)?;
+    }
+
+    let result = run_until_stopped(cpu, &mut save_file);
+
+    // Save even if emulation failed, so progress isn't lost.
+    if let Some(save_file) = save_file {
+        save_file.save(&cpu.memory)?;
+    }
+    result
+}
+
+fn run_until_stopped(cpu: &mut CPU, save_file: &mut Option<&mut SaveFile>)
+                     -> Result<(), String> {
+    let mut next_save = cpu.cycles + SAVE_INTERVAL;
+
+    while !cpu.stopped {
+        if let Some(limit) = cpu.cycle_limit {
+            if cpu.cycles >= limit {
+                break;
             }
-            None => {
-                return Err(
-                    format!("Could not decode instruction at {} bytes {:02X}",
-                            offset, bytes[offset]));
+        }
+
+        // HALT with every interrupt disabled never wakes up.
+        if cpu.halted && cpu.memory.read(IE_ADDRESS) & 0x1F == 0 {
+            break;
+        }
+
+        execute_next(cpu)?;
+
+        if cpu.cycles >= next_save {
+            if let Some(ref mut save_file) = *save_file {
+                save_file.save(&cpu.memory)?;
             }
+            next_save = cpu.cycles + SAVE_INTERVAL;
         }
     }
 
-    // TODO: return the CPU properly.
-    print!("Final CPU state: {:?}", cpu);
-
     Ok(())
 }
 
@@ -443,24 +1558,33 @@ fn decode_offset() {
     assert_eq!(instr, Nop);
 }
 
+#[test]
+fn decode_truncated() {
+    assert_eq!(decode(&[0xC3], 0), None);
+    assert_eq!(decode(&[0xC3, 0x50], 0), None);
+    assert_eq!(decode(&[0x00, 0x3E], 1), None);
+    assert_eq!(decode(&[0xCB], 0), None);
+    assert_eq!(decode(&[0x00], 1), None);
+    assert_eq!(decode(&[0xC3, 0x50, 0x01], 0).unwrap(),
+               Jump(Condition::Always, Operand16::Immediate(0x0150)));
+}
+
 #[test]
 fn step_nop() {
     let mut cpu = initial_cpu();
 
-    step(&mut cpu, Nop);
+    let cycles = step(&mut cpu, Nop).unwrap();
     assert_eq!(cpu.pc, Wrapping(1));
-    assert_eq!(cpu.m, Wrapping(1));
-    assert_eq!(cpu.t, Wrapping(4));
+    assert_eq!(cycles, 1);
 }
 
 #[test]
 fn step_inc() {
     let mut cpu = initial_cpu();
 
-    step(&mut cpu, Increment(Operand8::Register(A)));
+    let cycles = step(&mut cpu, Increment(Operand8::Register(A))).unwrap();
     assert_eq!(cpu.pc, Wrapping(1));
-    assert_eq!(cpu.m, Wrapping(1));
-    assert_eq!(cpu.t, Wrapping(4));
+    assert_eq!(cycles, 1);
 
     assert_eq!(cpu.a, Wrapping(1));
 }
@@ -470,7 +1594,7 @@ fn step_inc_wraps() {
     let mut cpu = initial_cpu();
     cpu.a = Wrapping(255);
 
-    step(&mut cpu, Increment(Operand8::Register(A)));
+    step(&mut cpu, Increment(Operand8::Register(A))).unwrap();
     assert_eq!(cpu.a, Wrapping(0));
 }
 
@@ -527,7 +1651,7 @@ fn step_xor_a() {
     let mut cpu = initial_cpu();
     cpu.a = Wrapping(5);
 
-    step(&mut cpu, decode(&bytes, 0).unwrap());
+    step(&mut cpu, decode(&bytes, 0).unwrap()).unwrap();
     assert_eq!(cpu.a, Wrapping(0));
 }
 
@@ -639,3 +1763,1395 @@ fn decode_rrc() {
     assert_eq!(decode(&bytes, 0).unwrap(),
                RotateLeftWithCarry(Operand8::Register(B)));
 }
+
+#[test]
+fn decode_illegal_opcodes() {
+    for opcode in ILLEGAL_OPCODES.iter() {
+        let bytes = [*opcode, 0x00, 0x00];
+        assert_eq!(decode(&bytes, 0), None);
+    }
+}
+
+// Every legal opcode outside of the 0xCB table, along with the
+// instruction it decodes to and its size. Operand bytes are always
+// 0xFE 0xCA.
+#[test]
+fn decode_base_opcodes() {
+    let expected = [
+        (0x00, Nop, 1),
+        (0x01, Load16(Operand16::Register(BC), Operand16::Immediate(0xCAFE)), 3),
+        (0x02, Load(Operand8::MemoryAddress(BC), Operand8::Register(A)), 1),
+        (0x03, Increment16(Operand16::Register(BC)), 1),
+        (0x04, Increment(Operand8::Register(B)), 1),
+        (0x05, Decrement(Operand8::Register(B)), 1),
+        (0x06, Load(Operand8::Register(B), Operand8::Immediate(0xFE)), 2),
+        (0x07, RotateLeftWithCarryA, 1),
+        (0x08,
+         Load16(Operand16::ConstantMemoryAddress(0xCAFE), Operand16::Register(SP)),
+         3),
+        (0x09, Add16(Operand16::Register(HL), Operand16::Register(BC)), 1),
+        (0x0A, Load(Operand8::Register(A), Operand8::MemoryAddress(BC)), 1),
+        (0x0B, Decrement16(Operand16::Register(BC)), 1),
+        (0x0C, Increment(Operand8::Register(C)), 1),
+        (0x0D, Decrement(Operand8::Register(C)), 1),
+        (0x0E, Load(Operand8::Register(C), Operand8::Immediate(0xFE)), 2),
+        (0x0F, RotateRightWithCarryA, 1),
+        (0x10, Stop, 2),
+        (0x11, Load16(Operand16::Register(DE), Operand16::Immediate(0xCAFE)), 3),
+        (0x12, Load(Operand8::MemoryAddress(DE), Operand8::Register(A)), 1),
+        (0x13, Increment16(Operand16::Register(DE)), 1),
+        (0x14, Increment(Operand8::Register(D)), 1),
+        (0x15, Decrement(Operand8::Register(D)), 1),
+        (0x16, Load(Operand8::Register(D), Operand8::Immediate(0xFE)), 2),
+        (0x17, RotateLeftA, 1),
+        (0x18, JumpRelative(Condition::Always, -2), 2),
+        (0x19, Add16(Operand16::Register(HL), Operand16::Register(DE)), 1),
+        (0x1A, Load(Operand8::Register(A), Operand8::MemoryAddress(DE)), 1),
+        (0x1B, Decrement16(Operand16::Register(DE)), 1),
+        (0x1C, Increment(Operand8::Register(E)), 1),
+        (0x1D, Decrement(Operand8::Register(E)), 1),
+        (0x1E, Load(Operand8::Register(E), Operand8::Immediate(0xFE)), 2),
+        (0x1F, RotateRightA, 1),
+        (0x20, JumpRelative(Condition::NonZero, -2), 2),
+        (0x21, Load16(Operand16::Register(HL), Operand16::Immediate(0xCAFE)), 3),
+        (0x22, LoadIncrement(Operand8::MemoryAddress(HL), Operand8::Register(A)), 1),
+        (0x23, Increment16(Operand16::Register(HL)), 1),
+        (0x24, Increment(Operand8::Register(H)), 1),
+        (0x25, Decrement(Operand8::Register(H)), 1),
+        (0x26, Load(Operand8::Register(H), Operand8::Immediate(0xFE)), 2),
+        (0x27, DecimalAdjust, 1),
+        (0x28, JumpRelative(Condition::Zero, -2), 2),
+        (0x29, Add16(Operand16::Register(HL), Operand16::Register(HL)), 1),
+        (0x2A, LoadIncrement(Operand8::Register(A), Operand8::MemoryAddress(HL)), 1),
+        (0x2B, Decrement16(Operand16::Register(HL)), 1),
+        (0x2C, Increment(Operand8::Register(L)), 1),
+        (0x2D, Decrement(Operand8::Register(L)), 1),
+        (0x2E, Load(Operand8::Register(L), Operand8::Immediate(0xFE)), 2),
+        (0x2F, Complement, 1),
+        (0x30, JumpRelative(Condition::NoCarry, -2), 2),
+        (0x31, Load16(Operand16::Register(SP), Operand16::Immediate(0xCAFE)), 3),
+        (0x32, LoadDecrement(Operand8::MemoryAddress(HL), Operand8::Register(A)), 1),
+        (0x33, Increment16(Operand16::Register(SP)), 1),
+        (0x34, Increment(Operand8::MemoryAddress(HL)), 1),
+        (0x35, Decrement(Operand8::MemoryAddress(HL)), 1),
+        (0x36, Load(Operand8::MemoryAddress(HL), Operand8::Immediate(0xFE)), 2),
+        (0x37, SetCarryFlag, 1),
+        (0x38, JumpRelative(Condition::Carry, -2), 2),
+        (0x39, Add16(Operand16::Register(HL), Operand16::Register(SP)), 1),
+        (0x3A, LoadDecrement(Operand8::Register(A), Operand8::MemoryAddress(HL)), 1),
+        (0x3B, Decrement16(Operand16::Register(SP)), 1),
+        (0x3C, Increment(Operand8::Register(A)), 1),
+        (0x3D, Decrement(Operand8::Register(A)), 1),
+        (0x3E, Load(Operand8::Register(A), Operand8::Immediate(0xFE)), 2),
+        (0x3F, ComplementCarryFlag, 1),
+        (0x40, Load(Operand8::Register(B), Operand8::Register(B)), 1),
+        (0x41, Load(Operand8::Register(B), Operand8::Register(C)), 1),
+        (0x42, Load(Operand8::Register(B), Operand8::Register(D)), 1),
+        (0x43, Load(Operand8::Register(B), Operand8::Register(E)), 1),
+        (0x44, Load(Operand8::Register(B), Operand8::Register(H)), 1),
+        (0x45, Load(Operand8::Register(B), Operand8::Register(L)), 1),
+        (0x46, Load(Operand8::Register(B), Operand8::MemoryAddress(HL)), 1),
+        (0x47, Load(Operand8::Register(B), Operand8::Register(A)), 1),
+        (0x48, Load(Operand8::Register(C), Operand8::Register(B)), 1),
+        (0x49, Load(Operand8::Register(C), Operand8::Register(C)), 1),
+        (0x4A, Load(Operand8::Register(C), Operand8::Register(D)), 1),
+        (0x4B, Load(Operand8::Register(C), Operand8::Register(E)), 1),
+        (0x4C, Load(Operand8::Register(C), Operand8::Register(H)), 1),
+        (0x4D, Load(Operand8::Register(C), Operand8::Register(L)), 1),
+        (0x4E, Load(Operand8::Register(C), Operand8::MemoryAddress(HL)), 1),
+        (0x4F, Load(Operand8::Register(C), Operand8::Register(A)), 1),
+        (0x50, Load(Operand8::Register(D), Operand8::Register(B)), 1),
+        (0x51, Load(Operand8::Register(D), Operand8::Register(C)), 1),
+        (0x52, Load(Operand8::Register(D), Operand8::Register(D)), 1),
+        (0x53, Load(Operand8::Register(D), Operand8::Register(E)), 1),
+        (0x54, Load(Operand8::Register(D), Operand8::Register(H)), 1),
+        (0x55, Load(Operand8::Register(D), Operand8::Register(L)), 1),
+        (0x56, Load(Operand8::Register(D), Operand8::MemoryAddress(HL)), 1),
+        (0x57, Load(Operand8::Register(D), Operand8::Register(A)), 1),
+        (0x58, Load(Operand8::Register(E), Operand8::Register(B)), 1),
+        (0x59, Load(Operand8::Register(E), Operand8::Register(C)), 1),
+        (0x5A, Load(Operand8::Register(E), Operand8::Register(D)), 1),
+        (0x5B, Load(Operand8::Register(E), Operand8::Register(E)), 1),
+        (0x5C, Load(Operand8::Register(E), Operand8::Register(H)), 1),
+        (0x5D, Load(Operand8::Register(E), Operand8::Register(L)), 1),
+        (0x5E, Load(Operand8::Register(E), Operand8::MemoryAddress(HL)), 1),
+        (0x5F, Load(Operand8::Register(E), Operand8::Register(A)), 1),
+        (0x60, Load(Operand8::Register(H), Operand8::Register(B)), 1),
+        (0x61, Load(Operand8::Register(H), Operand8::Register(C)), 1),
+        (0x62, Load(Operand8::Register(H), Operand8::Register(D)), 1),
+        (0x63, Load(Operand8::Register(H), Operand8::Register(E)), 1),
+        (0x64, Load(Operand8::Register(H), Operand8::Register(H)), 1),
+        (0x65, Load(Operand8::Register(H), Operand8::Register(L)), 1),
+        (0x66, Load(Operand8::Register(H), Operand8::MemoryAddress(HL)), 1),
+        (0x67, Load(Operand8::Register(H), Operand8::Register(A)), 1),
+        (0x68, Load(Operand8::Register(L), Operand8::Register(B)), 1),
+        (0x69, Load(Operand8::Register(L), Operand8::Register(C)), 1),
+        (0x6A, Load(Operand8::Register(L), Operand8::Register(D)), 1),
+        (0x6B, Load(Operand8::Register(L), Operand8::Register(E)), 1),
+        (0x6C, Load(Operand8::Register(L), Operand8::Register(H)), 1),
+        (0x6D, Load(Operand8::Register(L), Operand8::Register(L)), 1),
+        (0x6E, Load(Operand8::Register(L), Operand8::MemoryAddress(HL)), 1),
+        (0x6F, Load(Operand8::Register(L), Operand8::Register(A)), 1),
+        (0x70, Load(Operand8::MemoryAddress(HL), Operand8::Register(B)), 1),
+        (0x71, Load(Operand8::MemoryAddress(HL), Operand8::Register(C)), 1),
+        (0x72, Load(Operand8::MemoryAddress(HL), Operand8::Register(D)), 1),
+        (0x73, Load(Operand8::MemoryAddress(HL), Operand8::Register(E)), 1),
+        (0x74, Load(Operand8::MemoryAddress(HL), Operand8::Register(H)), 1),
+        (0x75, Load(Operand8::MemoryAddress(HL), Operand8::Register(L)), 1),
+        (0x76, Halt, 1),
+        (0x77, Load(Operand8::MemoryAddress(HL), Operand8::Register(A)), 1),
+        (0x78, Load(Operand8::Register(A), Operand8::Register(B)), 1),
+        (0x79, Load(Operand8::Register(A), Operand8::Register(C)), 1),
+        (0x7A, Load(Operand8::Register(A), Operand8::Register(D)), 1),
+        (0x7B, Load(Operand8::Register(A), Operand8::Register(E)), 1),
+        (0x7C, Load(Operand8::Register(A), Operand8::Register(H)), 1),
+        (0x7D, Load(Operand8::Register(A), Operand8::Register(L)), 1),
+        (0x7E, Load(Operand8::Register(A), Operand8::MemoryAddress(HL)), 1),
+        (0x7F, Load(Operand8::Register(A), Operand8::Register(A)), 1),
+        (0x80, Add(Operand8::Register(A), Operand8::Register(B)), 1),
+        (0x81, Add(Operand8::Register(A), Operand8::Register(C)), 1),
+        (0x82, Add(Operand8::Register(A), Operand8::Register(D)), 1),
+        (0x83, Add(Operand8::Register(A), Operand8::Register(E)), 1),
+        (0x84, Add(Operand8::Register(A), Operand8::Register(H)), 1),
+        (0x85, Add(Operand8::Register(A), Operand8::Register(L)), 1),
+        (0x86, Add(Operand8::Register(A), Operand8::MemoryAddress(HL)), 1),
+        (0x87, Add(Operand8::Register(A), Operand8::Register(A)), 1),
+        (0x88, AddWithCarry(Operand8::Register(A), Operand8::Register(B)), 1),
+        (0x89, AddWithCarry(Operand8::Register(A), Operand8::Register(C)), 1),
+        (0x8A, AddWithCarry(Operand8::Register(A), Operand8::Register(D)), 1),
+        (0x8B, AddWithCarry(Operand8::Register(A), Operand8::Register(E)), 1),
+        (0x8C, AddWithCarry(Operand8::Register(A), Operand8::Register(H)), 1),
+        (0x8D, AddWithCarry(Operand8::Register(A), Operand8::Register(L)), 1),
+        (0x8E, AddWithCarry(Operand8::Register(A), Operand8::MemoryAddress(HL)), 1),
+        (0x8F, AddWithCarry(Operand8::Register(A), Operand8::Register(A)), 1),
+        (0x90, Subtract(Operand8::Register(B)), 1),
+        (0x91, Subtract(Operand8::Register(C)), 1),
+        (0x92, Subtract(Operand8::Register(D)), 1),
+        (0x93, Subtract(Operand8::Register(E)), 1),
+        (0x94, Subtract(Operand8::Register(H)), 1),
+        (0x95, Subtract(Operand8::Register(L)), 1),
+        (0x96, Subtract(Operand8::MemoryAddress(HL)), 1),
+        (0x97, Subtract(Operand8::Register(A)), 1),
+        (0x98, SubtractWithCarry(Operand8::Register(A), Operand8::Register(B)), 1),
+        (0x99, SubtractWithCarry(Operand8::Register(A), Operand8::Register(C)), 1),
+        (0x9A, SubtractWithCarry(Operand8::Register(A), Operand8::Register(D)), 1),
+        (0x9B, SubtractWithCarry(Operand8::Register(A), Operand8::Register(E)), 1),
+        (0x9C, SubtractWithCarry(Operand8::Register(A), Operand8::Register(H)), 1),
+        (0x9D, SubtractWithCarry(Operand8::Register(A), Operand8::Register(L)), 1),
+        (0x9E, SubtractWithCarry(Operand8::Register(A), Operand8::MemoryAddress(HL)), 1),
+        (0x9F, SubtractWithCarry(Operand8::Register(A), Operand8::Register(A)), 1),
+        (0xA0, And(Operand8::Register(B)), 1),
+        (0xA1, And(Operand8::Register(C)), 1),
+        (0xA2, And(Operand8::Register(D)), 1),
+        (0xA3, And(Operand8::Register(E)), 1),
+        (0xA4, And(Operand8::Register(H)), 1),
+        (0xA5, And(Operand8::Register(L)), 1),
+        (0xA6, And(Operand8::MemoryAddress(HL)), 1),
+        (0xA7, And(Operand8::Register(A)), 1),
+        (0xA8, Xor(Operand8::Register(B)), 1),
+        (0xA9, Xor(Operand8::Register(C)), 1),
+        (0xAA, Xor(Operand8::Register(D)), 1),
+        (0xAB, Xor(Operand8::Register(E)), 1),
+        (0xAC, Xor(Operand8::Register(H)), 1),
+        (0xAD, Xor(Operand8::Register(L)), 1),
+        (0xAE, Xor(Operand8::MemoryAddress(HL)), 1),
+        (0xAF, Xor(Operand8::Register(A)), 1),
+        (0xB0, Or(Operand8::Register(B)), 1),
+        (0xB1, Or(Operand8::Register(C)), 1),
+        (0xB2, Or(Operand8::Register(D)), 1),
+        (0xB3, Or(Operand8::Register(E)), 1),
+        (0xB4, Or(Operand8::Register(H)), 1),
+        (0xB5, Or(Operand8::Register(L)), 1),
+        (0xB6, Or(Operand8::MemoryAddress(HL)), 1),
+        (0xB7, Or(Operand8::Register(A)), 1),
+        (0xB8, Compare(Operand8::Register(B)), 1),
+        (0xB9, Compare(Operand8::Register(C)), 1),
+        (0xBA, Compare(Operand8::Register(D)), 1),
+        (0xBB, Compare(Operand8::Register(E)), 1),
+        (0xBC, Compare(Operand8::Register(H)), 1),
+        (0xBD, Compare(Operand8::Register(L)), 1),
+        (0xBE, Compare(Operand8::MemoryAddress(HL)), 1),
+        (0xBF, Compare(Operand8::Register(A)), 1),
+        (0xC0, Return(Condition::NonZero), 1),
+        (0xC1, Pop(BC), 1),
+        (0xC2, Jump(Condition::NonZero, Operand16::Immediate(0xCAFE)), 3),
+        (0xC3, Jump(Condition::Always, Operand16::Immediate(0xCAFE)), 3),
+        (0xC4, Call(Condition::NonZero, 0xCAFE), 3),
+        (0xC5, Push(BC), 1),
+        (0xC6, Add(Operand8::Register(A), Operand8::Immediate(0xFE)), 2),
+        (0xC7, Restart(0x00), 1),
+        (0xC8, Return(Condition::Zero), 1),
+        (0xC9, Return(Condition::Always), 1),
+        (0xCA, Jump(Condition::Zero, Operand16::Immediate(0xCAFE)), 3),
+        (0xCC, Call(Condition::Zero, 0xCAFE), 3),
+        (0xCD, Call(Condition::Always, 0xCAFE), 3),
+        (0xCE, AddWithCarry(Operand8::Register(A), Operand8::Immediate(0xFE)), 2),
+        (0xCF, Restart(0x08), 1),
+        (0xD0, Return(Condition::NoCarry), 1),
+        (0xD1, Pop(DE), 1),
+        (0xD2, Jump(Condition::NoCarry, Operand16::Immediate(0xCAFE)), 3),
+        (0xD4, Call(Condition::NoCarry, 0xCAFE), 3),
+        (0xD5, Push(DE), 1),
+        (0xD6, Subtract(Operand8::Immediate(0xFE)), 2),
+        (0xD7, Restart(0x10), 1),
+        (0xD8, Return(Condition::Carry), 1),
+        (0xD9, ReturnFromInterrupt, 1),
+        (0xDA, Jump(Condition::Carry, Operand16::Immediate(0xCAFE)), 3),
+        (0xDC, Call(Condition::Carry, 0xCAFE), 3),
+        (0xDE, SubtractWithCarry(Operand8::Register(A), Operand8::Immediate(0xFE)), 2),
+        (0xDF, Restart(0x18), 1),
+        (0xE0,
+         Load(Operand8::ConstantMemoryAddressWithOffset(0xFE, 0xFF00), Operand8::Register(A)),
+         2),
+        (0xE1, Pop(HL), 1),
+        (0xE2,
+         Load(Operand8::MemoryAddressWithOffset(C, 0xFF00), Operand8::Register(A)),
+         1),
+        (0xE5, Push(HL), 1),
+        (0xE6, And(Operand8::Immediate(0xFE)), 2),
+        (0xE7, Restart(0x20), 1),
+        (0xE8, Add16(Operand16::Register(SP), Operand16::SignedImmediate(-2)), 2),
+        (0xE9, Jump(Condition::Always, Operand16::Register(HL)), 1),
+        (0xEA, Load(Operand8::ConstantMemoryAddress(0xCAFE), Operand8::Register(A)), 3),
+        (0xEE, Xor(Operand8::Immediate(0xFE)), 2),
+        (0xEF, Restart(0x28), 1),
+        (0xF0,
+         Load(Operand8::Register(A), Operand8::ConstantMemoryAddressWithOffset(0xFE, 0xFF00)),
+         2),
+        (0xF1, Pop(AF), 1),
+        (0xF2,
+         Load(Operand8::Register(A), Operand8::MemoryAddressWithOffset(C, 0xFF00)),
+         1),
+        (0xF3, DisableInterrupts, 1),
+        (0xF5, Push(AF), 1),
+        (0xF6, Or(Operand8::Immediate(0xFE)), 2),
+        (0xF7, Restart(0x30), 1),
+        (0xF8, Load16(Operand16::Register(HL), Operand16::StackPointerWithOffset(-2)), 2),
+        (0xF9, Load16(Operand16::Register(SP), Operand16::Register(HL)), 1),
+        (0xFA, Load(Operand8::Register(A), Operand8::ConstantMemoryAddress(0xCAFE)), 3),
+        (0xFB, EnableInterrupts, 1),
+        (0xFE, Compare(Operand8::Immediate(0xFE)), 2),
+        (0xFF, Restart(0x38), 1),
+    ];
+
+    for &(opcode, ref instr, size) in expected.iter() {
+        let bytes = [opcode, 0xFE, 0xCA];
+        assert_eq!(decode(&bytes, 0).as_ref(), Some(instr),
+                   "decoding opcode {:02X}", opcode);
+        assert_eq!(instr_size(instr), size,
+                   "size of opcode {:02X}", opcode);
+    }
+
+    // Every opcode is either the 0xCB prefix, illegal, or covered above.
+    assert_eq!(expected.len(), 256 - 1 - ILLEGAL_OPCODES.len());
+}
+
+#[test]
+fn decode_ldh() {
+    let bytes = [0xF0, 0x44];
+    let instr = decode(&bytes, 0).unwrap();
+
+    assert_eq!(instr,
+               Load(Operand8::Register(A),
+                    Operand8::ConstantMemoryAddressWithOffset(0x44, 0xFF00)));
+    assert_eq!(instr_size(&instr), 2);
+}
+
+#[test]
+fn rotate_size() {
+    // RLCA is a single byte, whereas RLC A needs the 0xCB prefix.
+    assert_eq!(instr_size(&decode(&[0x07], 0).unwrap()), 1);
+    assert_eq!(instr_size(&decode(&[0xCB, 0x07], 0).unwrap()), 2);
+}
+
+#[test]
+fn decode_cb_opcodes() {
+    for byte2 in 0..256u16 {
+        let bytes = [0xCB, byte2 as u8];
+        let instr = decode(&bytes, 0).unwrap();
+        assert_eq!(instr_size(&instr), 2, "size of CB {:02X}", byte2);
+    }
+}
+
+#[test]
+fn decode_cb_bit_ops() {
+    assert_eq!(decode(&[0xCB, 0xCE], 0).unwrap(),
+               Set(1, Operand8::MemoryAddress(HL)));
+    assert_eq!(decode(&[0xCB, 0xBF], 0).unwrap(),
+               Reset(7, Operand8::Register(A)));
+    assert_eq!(decode(&[0xCB, 0x61], 0).unwrap(),
+               Bit(4, Operand8::Register(C)));
+}
+
+#[test]
+fn decode_cb_shifts() {
+    assert_eq!(decode(&[0xCB, 0x11], 0).unwrap(),
+               RotateLeft(Operand8::Register(C)));
+    assert_eq!(decode(&[0xCB, 0x1F], 0).unwrap(),
+               RotateRight(Operand8::Register(A)));
+    assert_eq!(decode(&[0xCB, 0x22], 0).unwrap(),
+               ShiftLeftArithmetic(Operand8::Register(D)));
+    assert_eq!(decode(&[0xCB, 0x2D], 0).unwrap(),
+               ShiftRightArithmetic(Operand8::Register(L)));
+    assert_eq!(decode(&[0xCB, 0x33], 0).unwrap(),
+               Swap(Operand8::Register(E)));
+    assert_eq!(decode(&[0xCB, 0x3E], 0).unwrap(),
+               ShiftRightLogical(Operand8::MemoryAddress(HL)));
+}
+
+#[test]
+fn step_set_memory() {
+    let mut cpu = initial_cpu();
+    cpu.h = Wrapping(0xC0);
+    cpu.l = Wrapping(0x10);
+
+    step(&mut cpu, Set(1, Operand8::MemoryAddress(HL))).unwrap();
+    assert_eq!(cpu.memory.read(0xC010), 0x02);
+
+    step(&mut cpu, Reset(1, Operand8::MemoryAddress(HL))).unwrap();
+    assert_eq!(cpu.memory.read(0xC010), 0x00);
+}
+
+#[test]
+fn step_bit() {
+    let mut cpu = initial_cpu();
+    cpu.b = Wrapping(0x80);
+    cpu.flags = Flags::new(false, false, false, true);
+
+    step(&mut cpu, Bit(7, Operand8::Register(B))).unwrap();
+    assert_eq!(cpu.flags, Flags::new(false, false, true, true));
+
+    step(&mut cpu, Bit(6, Operand8::Register(B))).unwrap();
+    assert_eq!(cpu.flags, Flags::new(true, false, true, true));
+}
+
+#[test]
+fn step_rotate_through_carry() {
+    let mut cpu = initial_cpu();
+    cpu.c = Wrapping(0x80);
+
+    // RL C: bit 7 goes into the carry, and the old carry (0) into bit 0.
+    step(&mut cpu, RotateLeft(Operand8::Register(C))).unwrap();
+    assert_eq!(cpu.c, Wrapping(0x00));
+    assert_eq!(cpu.flags, Flags::new(true, false, false, true));
+
+    // RR C: the carry comes back in at bit 7.
+    step(&mut cpu, RotateRight(Operand8::Register(C))).unwrap();
+    assert_eq!(cpu.c, Wrapping(0x80));
+    assert_eq!(cpu.flags, Flags::default());
+}
+
+#[test]
+fn step_rlc() {
+    let mut cpu = initial_cpu();
+    cpu.a = Wrapping(0x85);
+
+    step(&mut cpu, RotateLeftWithCarry(Operand8::Register(A))).unwrap();
+    assert_eq!(cpu.a, Wrapping(0x0B));
+    assert_eq!(cpu.flags, Flags::new(false, false, false, true));
+}
+
+#[test]
+fn step_shifts() {
+    let mut cpu = initial_cpu();
+    cpu.d = Wrapping(0x81);
+
+    step(&mut cpu, ShiftRightArithmetic(Operand8::Register(D))).unwrap();
+    assert_eq!(cpu.d, Wrapping(0xC0));
+    assert_eq!(cpu.flags, Flags::new(false, false, false, true));
+
+    step(&mut cpu, ShiftRightLogical(Operand8::Register(D))).unwrap();
+    assert_eq!(cpu.d, Wrapping(0x60));
+    assert_eq!(cpu.flags, Flags::default());
+
+    step(&mut cpu, ShiftLeftArithmetic(Operand8::Register(D))).unwrap();
+    assert_eq!(cpu.d, Wrapping(0xC0));
+    assert_eq!(cpu.flags, Flags::default());
+}
+
+#[test]
+fn step_swap() {
+    let mut cpu = initial_cpu();
+    cpu.e = Wrapping(0xF1);
+    cpu.flags = Flags::new(false, false, false, true);
+
+    step(&mut cpu, Swap(Operand8::Register(E))).unwrap();
+    assert_eq!(cpu.e, Wrapping(0x1F));
+    assert_eq!(cpu.flags, Flags::default());
+
+    cpu.e = Wrapping(0);
+    step(&mut cpu, Swap(Operand8::Register(E))).unwrap();
+    assert_eq!(cpu.flags, Flags::new(true, false, false, false));
+}
+
+#[test]
+fn step_inc_flags() {
+    let mut cpu = initial_cpu();
+    cpu.b = Wrapping(0x0F);
+    cpu.flags = Flags::new(false, true, false, true);
+
+    // Half carry out of bit 3, carry is preserved.
+    step(&mut cpu, Increment(Operand8::Register(B))).unwrap();
+    assert_eq!(cpu.b, Wrapping(0x10));
+    assert_eq!(cpu.flags, Flags::new(false, false, true, true));
+
+    cpu.b = Wrapping(0xFF);
+    cpu.flags = Flags::default();
+    step(&mut cpu, Increment(Operand8::Register(B))).unwrap();
+    assert_eq!(cpu.b, Wrapping(0x00));
+    assert_eq!(cpu.flags, Flags::new(true, false, true, false));
+}
+
+#[test]
+fn step_dec_flags() {
+    let mut cpu = initial_cpu();
+    cpu.b = Wrapping(0x10);
+
+    // Borrow from bit 4.
+    step(&mut cpu, Decrement(Operand8::Register(B))).unwrap();
+    assert_eq!(cpu.b, Wrapping(0x0F));
+    assert_eq!(cpu.flags, Flags::new(false, true, true, false));
+
+    cpu.b = Wrapping(0x01);
+    step(&mut cpu, Decrement(Operand8::Register(B))).unwrap();
+    assert_eq!(cpu.b, Wrapping(0x00));
+    assert_eq!(cpu.flags, Flags::new(true, true, false, false));
+
+    // DEC 0 wraps, but doesn't touch the carry.
+    step(&mut cpu, Decrement(Operand8::Register(B))).unwrap();
+    assert_eq!(cpu.b, Wrapping(0xFF));
+    assert_eq!(cpu.flags, Flags::new(false, true, true, false));
+}
+
+#[test]
+fn step_add_flags() {
+    let mut cpu = initial_cpu();
+    cpu.a = Wrapping(0x3A);
+    cpu.b = Wrapping(0xC6);
+
+    step(&mut cpu, Add(Operand8::Register(A), Operand8::Register(B))).unwrap();
+    assert_eq!(cpu.a, Wrapping(0x00));
+    assert_eq!(cpu.flags, Flags::new(true, false, true, true));
+
+    cpu.a = Wrapping(0x3C);
+    step(&mut cpu, Add(Operand8::Register(A), Operand8::Immediate(0x12))).unwrap();
+    assert_eq!(cpu.a, Wrapping(0x4E));
+    assert_eq!(cpu.flags, Flags::default());
+}
+
+#[test]
+fn step_adc_flags() {
+    let mut cpu = initial_cpu();
+    cpu.a = Wrapping(0xE1);
+    cpu.e = Wrapping(0x0F);
+    cpu.flags = Flags::new(false, false, false, true);
+
+    step(&mut cpu, AddWithCarry(Operand8::Register(A), Operand8::Register(E))).unwrap();
+    assert_eq!(cpu.a, Wrapping(0xF1));
+    assert_eq!(cpu.flags, Flags::new(false, false, true, false));
+
+    // The carry alone can cause both carries.
+    cpu.a = Wrapping(0xFF);
+    cpu.flags = Flags::new(false, false, false, true);
+    step(&mut cpu, AddWithCarry(Operand8::Register(A), Operand8::Immediate(0))).unwrap();
+    assert_eq!(cpu.a, Wrapping(0x00));
+    assert_eq!(cpu.flags, Flags::new(true, false, true, true));
+}
+
+#[test]
+fn step_sub_flags() {
+    let mut cpu = initial_cpu();
+    cpu.a = Wrapping(0x3E);
+    cpu.e = Wrapping(0x3E);
+
+    step(&mut cpu, Subtract(Operand8::Register(E))).unwrap();
+    assert_eq!(cpu.a, Wrapping(0x00));
+    assert_eq!(cpu.flags, Flags::new(true, true, false, false));
+
+    cpu.a = Wrapping(0x3E);
+    step(&mut cpu, Subtract(Operand8::Immediate(0x0F))).unwrap();
+    assert_eq!(cpu.a, Wrapping(0x2F));
+    assert_eq!(cpu.flags, Flags::new(false, true, true, false));
+
+    cpu.a = Wrapping(0x3E);
+    step(&mut cpu, Subtract(Operand8::Immediate(0x40))).unwrap();
+    assert_eq!(cpu.a, Wrapping(0xFE));
+    assert_eq!(cpu.flags, Flags::new(false, true, false, true));
+}
+
+#[test]
+fn step_sbc_flags() {
+    let mut cpu = initial_cpu();
+    cpu.a = Wrapping(0x3B);
+    cpu.h = Wrapping(0x2A);
+    cpu.flags = Flags::new(false, false, false, true);
+
+    step(&mut cpu, SubtractWithCarry(Operand8::Register(A), Operand8::Register(H))).unwrap();
+    assert_eq!(cpu.a, Wrapping(0x10));
+    assert_eq!(cpu.flags, Flags::new(false, true, false, false));
+
+    // Only the carry pushes this into a borrow.
+    cpu.a = Wrapping(0x00);
+    cpu.flags = Flags::new(false, false, false, true);
+    step(&mut cpu, SubtractWithCarry(Operand8::Register(A), Operand8::Immediate(0))).unwrap();
+    assert_eq!(cpu.a, Wrapping(0xFF));
+    assert_eq!(cpu.flags, Flags::new(false, true, true, true));
+}
+
+#[test]
+fn step_cp_flags() {
+    let mut cpu = initial_cpu();
+    cpu.a = Wrapping(0x3C);
+    cpu.b = Wrapping(0x2F);
+
+    step(&mut cpu, Compare(Operand8::Register(B))).unwrap();
+    assert_eq!(cpu.a, Wrapping(0x3C));
+    assert_eq!(cpu.flags, Flags::new(false, true, true, false));
+
+    step(&mut cpu, Compare(Operand8::Immediate(0x3C))).unwrap();
+    assert_eq!(cpu.flags, Flags::new(true, true, false, false));
+
+    step(&mut cpu, Compare(Operand8::Immediate(0x40))).unwrap();
+    assert_eq!(cpu.flags, Flags::new(false, true, false, true));
+}
+
+#[test]
+fn step_logic_flags() {
+    let mut cpu = initial_cpu();
+    cpu.a = Wrapping(0x5A);
+    cpu.flags = Flags::new(false, true, false, true);
+
+    // AND always sets the half carry.
+    step(&mut cpu, And(Operand8::Immediate(0x3F))).unwrap();
+    assert_eq!(cpu.a, Wrapping(0x1A));
+    assert_eq!(cpu.flags, Flags::new(false, false, true, false));
+
+    step(&mut cpu, And(Operand8::Immediate(0x00))).unwrap();
+    assert_eq!(cpu.flags, Flags::new(true, false, true, false));
+
+    step(&mut cpu, Or(Operand8::Immediate(0x00))).unwrap();
+    assert_eq!(cpu.flags, Flags::new(true, false, false, false));
+
+    cpu.a = Wrapping(0xFF);
+    cpu.flags = Flags::new(false, true, true, true);
+    step(&mut cpu, Xor(Operand8::Immediate(0x0F))).unwrap();
+    assert_eq!(cpu.a, Wrapping(0xF0));
+    assert_eq!(cpu.flags, Flags::default());
+}
+
+#[test]
+fn step_rla() {
+    let mut cpu = initial_cpu();
+    cpu.a = Wrapping(0x80);
+
+    // Unlike RL A, RLA never sets the zero flag.
+    step(&mut cpu, RotateLeftA).unwrap();
+    assert_eq!(cpu.a, Wrapping(0x00));
+    assert_eq!(cpu.flags, Flags::new(false, false, false, true));
+}
+
+#[test]
+fn step_jr() {
+    let mut cpu = initial_cpu();
+    cpu.pc = Wrapping(0x0100);
+
+    // JR NZ is taken when Z is clear, and is relative to the next
+    // instruction.
+    let cycles = step(&mut cpu, JumpRelative(Condition::NonZero, -5)).unwrap();
+    assert_eq!(cpu.pc, Wrapping(0x00FD));
+    assert_eq!(cycles, 3);
+
+    cpu.flags.set_zero(true);
+    let cycles = step(&mut cpu, JumpRelative(Condition::NonZero, -5)).unwrap();
+    assert_eq!(cpu.pc, Wrapping(0x00FF));
+    assert_eq!(cycles, 2);
+}
+
+#[test]
+fn step_jp() {
+    let mut cpu = initial_cpu();
+    cpu.flags = Flags::new(false, false, false, true);
+
+    let cycles = step(&mut cpu, Jump(Condition::NoCarry, Operand16::Immediate(0x1234))).unwrap();
+    assert_eq!(cpu.pc, Wrapping(0x0003));
+    assert_eq!(cycles, 3);
+
+    let cycles = step(&mut cpu, Jump(Condition::Carry, Operand16::Immediate(0x1234))).unwrap();
+    assert_eq!(cpu.pc, Wrapping(0x1234));
+    assert_eq!(cycles, 4);
+
+    cpu.h = Wrapping(0xC0);
+    cpu.l = Wrapping(0xDE);
+    let cycles = step(&mut cpu, Jump(Condition::Always, Operand16::Register(HL))).unwrap();
+    assert_eq!(cpu.pc, Wrapping(0xC0DE));
+    assert_eq!(cycles, 1);
+}
+
+#[test]
+fn step_call_ret() {
+    let mut cpu = initial_cpu();
+    cpu.pc = Wrapping(0x0150);
+    cpu.sp = Wrapping(0xFFFE);
+
+    let cycles = step(&mut cpu, Call(Condition::Always, 0x2000)).unwrap();
+    assert_eq!(cpu.pc, Wrapping(0x2000));
+    assert_eq!(cpu.sp, Wrapping(0xFFFC));
+    assert_eq!(cpu.memory.read(0xFFFD), 0x01);
+    assert_eq!(cpu.memory.read(0xFFFC), 0x53);
+    assert_eq!(cycles, 6);
+
+    // RET Z isn't taken, since Z is clear.
+    let cycles = step(&mut cpu, Return(Condition::Zero)).unwrap();
+    assert_eq!(cpu.pc, Wrapping(0x2001));
+    assert_eq!(cycles, 2);
+
+    let cycles = step(&mut cpu, Return(Condition::NonZero)).unwrap();
+    assert_eq!(cpu.pc, Wrapping(0x0153));
+    assert_eq!(cpu.sp, Wrapping(0xFFFE));
+    assert_eq!(cycles, 5);
+}
+
+#[test]
+fn step_call_not_taken() {
+    let mut cpu = initial_cpu();
+    cpu.sp = Wrapping(0xFFFE);
+
+    let cycles = step(&mut cpu, Call(Condition::Carry, 0x2000)).unwrap();
+    assert_eq!(cpu.pc, Wrapping(0x0003));
+    assert_eq!(cpu.sp, Wrapping(0xFFFE));
+    assert_eq!(cycles, 3);
+}
+
+#[test]
+fn step_rst_reti() {
+    let mut cpu = initial_cpu();
+    cpu.pc = Wrapping(0x0200);
+    cpu.sp = Wrapping(0xFFFE);
+
+    let cycles = step(&mut cpu, Restart(0x38)).unwrap();
+    assert_eq!(cpu.pc, Wrapping(0x0038));
+    assert_eq!(cycles, 4);
+
+    step(&mut cpu, ReturnFromInterrupt).unwrap();
+    assert_eq!(cpu.pc, Wrapping(0x0201));
+    assert_eq!(cpu.sp, Wrapping(0xFFFE));
+    assert!(cpu.ime);
+}
+
+#[test]
+fn step_ld_hl_immediate() {
+    let mut cpu = initial_cpu();
+
+    step(&mut cpu, Load16(Operand16::Register(HL), Operand16::Immediate(0x9FFF))).unwrap();
+    assert_eq!(cpu.h, Wrapping(0x9F));
+    assert_eq!(cpu.l, Wrapping(0xFF));
+}
+
+#[test]
+fn step_push_pop() {
+    let mut cpu = initial_cpu();
+    cpu.sp = Wrapping(0xFFFE);
+    cpu.b = Wrapping(0x12);
+    cpu.c = Wrapping(0x34);
+
+    let cycles = step(&mut cpu, Push(BC)).unwrap();
+    assert_eq!(cpu.sp, Wrapping(0xFFFC));
+    assert_eq!(cycles, 4);
+
+    let cycles = step(&mut cpu, Pop(DE)).unwrap();
+    assert_eq!(cpu.sp, Wrapping(0xFFFE));
+    assert_eq!(cpu.d, Wrapping(0x12));
+    assert_eq!(cpu.e, Wrapping(0x34));
+    assert_eq!(cycles, 3);
+}
+
+#[test]
+fn step_pop_af_masks_flags() {
+    let mut cpu = initial_cpu();
+    cpu.sp = Wrapping(0xFFFE);
+    cpu.b = Wrapping(0xAB);
+    cpu.c = Wrapping(0xFF);
+
+    step(&mut cpu, Push(BC)).unwrap();
+    step(&mut cpu, Pop(AF)).unwrap();
+    assert_eq!(cpu.a, Wrapping(0xAB));
+    assert_eq!(cpu.flags.to_byte(), 0xF0);
+
+    step(&mut cpu, Push(AF)).unwrap();
+    assert_eq!(cpu.memory.read(0xFFFC), 0xF0);
+}
+
+#[test]
+fn step_inc_dec16() {
+    let mut cpu = initial_cpu();
+    cpu.d = Wrapping(0x00);
+    cpu.e = Wrapping(0xFF);
+
+    step(&mut cpu, Increment16(Operand16::Register(DE))).unwrap();
+    assert_eq!(cpu.d, Wrapping(0x01));
+    assert_eq!(cpu.e, Wrapping(0x00));
+
+    step(&mut cpu, Decrement16(Operand16::Register(SP))).unwrap();
+    assert_eq!(cpu.sp, Wrapping(0xFFFF));
+    assert_eq!(cpu.flags, Flags::default());
+}
+
+#[test]
+fn step_add_hl() {
+    let mut cpu = initial_cpu();
+    cpu.h = Wrapping(0x8A);
+    cpu.l = Wrapping(0x23);
+    cpu.b = Wrapping(0x06);
+    cpu.c = Wrapping(0x05);
+    cpu.flags.set_zero(true);
+
+    step(&mut cpu, Add16(Operand16::Register(HL), Operand16::Register(BC))).unwrap();
+    assert_eq!(cpu.h, Wrapping(0x90));
+    assert_eq!(cpu.l, Wrapping(0x28));
+    assert_eq!(cpu.flags, Flags::new(true, false, true, false));
+
+    step(&mut cpu, Add16(Operand16::Register(HL), Operand16::Register(HL))).unwrap();
+    assert_eq!(cpu.h, Wrapping(0x20));
+    assert_eq!(cpu.l, Wrapping(0x50));
+    assert_eq!(cpu.flags, Flags::new(true, false, false, true));
+}
+
+#[test]
+fn step_daa_after_add() {
+    let mut cpu = initial_cpu();
+    cpu.a = Wrapping(0x45);
+
+    step(&mut cpu, Add(Operand8::Register(A), Operand8::Immediate(0x38))).unwrap();
+    step(&mut cpu, DecimalAdjust).unwrap();
+    assert_eq!(cpu.a, Wrapping(0x83));
+    assert_eq!(cpu.flags, Flags::default());
+
+    // 99 + 1 = 100, which overflows into the carry.
+    cpu.a = Wrapping(0x99);
+    step(&mut cpu, Add(Operand8::Register(A), Operand8::Immediate(0x01))).unwrap();
+    step(&mut cpu, DecimalAdjust).unwrap();
+    assert_eq!(cpu.a, Wrapping(0x00));
+    assert_eq!(cpu.flags, Flags::new(true, false, false, true));
+}
+
+#[test]
+fn step_daa_after_sub() {
+    let mut cpu = initial_cpu();
+    cpu.a = Wrapping(0x83);
+
+    step(&mut cpu, Subtract(Operand8::Immediate(0x38))).unwrap();
+    step(&mut cpu, DecimalAdjust).unwrap();
+    assert_eq!(cpu.a, Wrapping(0x45));
+    assert_eq!(cpu.flags, Flags::new(false, true, false, false));
+
+    // 10 - 20 borrows, giving 90.
+    cpu.a = Wrapping(0x10);
+    step(&mut cpu, Subtract(Operand8::Immediate(0x20))).unwrap();
+    step(&mut cpu, DecimalAdjust).unwrap();
+    assert_eq!(cpu.a, Wrapping(0x90));
+    assert_eq!(cpu.flags, Flags::new(false, true, false, true));
+}
+
+#[test]
+fn step_cpl() {
+    let mut cpu = initial_cpu();
+    cpu.a = Wrapping(0x35);
+
+    step(&mut cpu, Complement).unwrap();
+    assert_eq!(cpu.a, Wrapping(0xCA));
+    assert_eq!(cpu.flags, Flags::new(false, true, true, false));
+}
+
+#[test]
+fn step_scf_ccf() {
+    let mut cpu = initial_cpu();
+    cpu.flags = Flags::new(true, true, true, false);
+
+    step(&mut cpu, SetCarryFlag).unwrap();
+    assert_eq!(cpu.flags, Flags::new(true, false, false, true));
+
+    step(&mut cpu, ComplementCarryFlag).unwrap();
+    assert_eq!(cpu.flags, Flags::new(true, false, false, false));
+
+    step(&mut cpu, ComplementCarryFlag).unwrap();
+    assert_eq!(cpu.flags, Flags::new(true, false, false, true));
+}
+
+#[test]
+fn step_alu_memory_operand() {
+    let mut cpu = initial_cpu();
+    cpu.h = Wrapping(0xC0);
+    cpu.l = Wrapping(0x00);
+    cpu.memory.write(0xC000, 0x0F);
+    cpu.a = Wrapping(0x01);
+    cpu.flags.set_carry(true);
+
+    step(&mut cpu, AddWithCarry(Operand8::Register(A), Operand8::MemoryAddress(HL))).unwrap();
+    assert_eq!(cpu.a, Wrapping(0x11));
+    assert_eq!(cpu.flags, Flags::new(false, false, true, false));
+
+    step(&mut cpu, And(Operand8::MemoryAddress(HL))).unwrap();
+    assert_eq!(cpu.a, Wrapping(0x01));
+
+    step(&mut cpu, Compare(Operand8::MemoryAddress(HL))).unwrap();
+    assert_eq!(cpu.flags, Flags::new(false, true, true, true));
+}
+
+#[test]
+fn step_ld_register() {
+    let mut cpu = initial_cpu();
+    cpu.e = Wrapping(0x42);
+
+    step(&mut cpu, Load(Operand8::Register(B), Operand8::Register(E))).unwrap();
+    assert_eq!(cpu.b, Wrapping(0x42));
+}
+
+#[test]
+fn step_ldh() {
+    let mut cpu = initial_cpu();
+    cpu.a = Wrapping(0x91);
+
+    step(&mut cpu, Load(Operand8::ConstantMemoryAddressWithOffset(0x40, 0xFF00),
+                        Operand8::Register(A))).unwrap();
+    assert_eq!(cpu.memory.read(0xFF40), 0x91);
+
+    cpu.c = Wrapping(0x40);
+    step(&mut cpu, Load(Operand8::Register(B),
+                        Operand8::MemoryAddressWithOffset(C, 0xFF00))).unwrap();
+    assert_eq!(cpu.b, Wrapping(0x91));
+}
+
+#[test]
+fn step_ld_constant_address() {
+    let mut cpu = initial_cpu();
+    cpu.memory.write(0xC123, 0x77);
+
+    step(&mut cpu, Load(Operand8::Register(A),
+                        Operand8::ConstantMemoryAddress(0xC123))).unwrap();
+    assert_eq!(cpu.a, Wrapping(0x77));
+
+    step(&mut cpu, Load(Operand8::ConstantMemoryAddress(0xC124),
+                        Operand8::Register(A))).unwrap();
+    assert_eq!(cpu.memory.read(0xC124), 0x77);
+}
+
+#[test]
+fn step_ld_increment_decrement() {
+    let mut cpu = initial_cpu();
+    cpu.h = Wrapping(0xC0);
+    cpu.l = Wrapping(0xFF);
+    cpu.a = Wrapping(0x11);
+
+    step(&mut cpu, LoadIncrement(Operand8::MemoryAddress(HL), Operand8::Register(A))).unwrap();
+    assert_eq!(cpu.memory.read(0xC0FF), 0x11);
+    assert_eq!(cpu.h, Wrapping(0xC1));
+    assert_eq!(cpu.l, Wrapping(0x00));
+
+    cpu.memory.write(0xC100, 0x22);
+    step(&mut cpu, LoadDecrement(Operand8::Register(A), Operand8::MemoryAddress(HL))).unwrap();
+    assert_eq!(cpu.a, Wrapping(0x22));
+    assert_eq!(cpu.h, Wrapping(0xC0));
+    assert_eq!(cpu.l, Wrapping(0xFF));
+}
+
+#[test]
+fn step_ld_address_sp() {
+    let mut cpu = initial_cpu();
+    cpu.sp = Wrapping(0xFFF8);
+
+    step(&mut cpu, Load16(Operand16::ConstantMemoryAddress(0xC100),
+                          Operand16::Register(SP))).unwrap();
+    assert_eq!(cpu.memory.read(0xC100), 0xF8);
+    assert_eq!(cpu.memory.read(0xC101), 0xFF);
+}
+
+#[test]
+fn step_add_sp() {
+    let mut cpu = initial_cpu();
+    cpu.sp = Wrapping(0xFFF8);
+    cpu.flags.set_zero(true);
+
+    step(&mut cpu, Add16(Operand16::Register(SP), Operand16::SignedImmediate(2))).unwrap();
+    assert_eq!(cpu.sp, Wrapping(0xFFFA));
+    assert_eq!(cpu.flags, Flags::default());
+
+    // Negative offsets still compute carries from the low byte.
+    step(&mut cpu, Add16(Operand16::Register(SP), Operand16::SignedImmediate(-1))).unwrap();
+    assert_eq!(cpu.sp, Wrapping(0xFFF9));
+    assert_eq!(cpu.flags, Flags::new(false, false, true, true));
+}
+
+#[test]
+fn step_ld_hl_sp_offset() {
+    let mut cpu = initial_cpu();
+    cpu.sp = Wrapping(0xFFF8);
+
+    step(&mut cpu, Load16(Operand16::Register(HL),
+                          Operand16::StackPointerWithOffset(8))).unwrap();
+    assert_eq!(cpu.h, Wrapping(0x00));
+    assert_eq!(cpu.l, Wrapping(0x00));
+    assert_eq!(cpu.sp, Wrapping(0xFFF8));
+    assert_eq!(cpu.flags, Flags::new(false, false, true, true));
+}
+
+// A ROM with the given program at the cartridge entry point.
+#[cfg(test)]
+fn rom_with_program(program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 0x100];
+    rom.extend_from_slice(program);
+    rom
+}
+
+#[test]
+fn post_boot_state() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x014D] = 0x42;
+    let cpu = load_cartridge(&rom, Model::Dmg, None).unwrap();
+
+    assert_eq!(cpu.a, Wrapping(0x01));
+    assert_eq!(cpu.flags, Flags::new(true, false, true, true));
+    assert_eq!(read_register16(&cpu, BC), 0x0013);
+    assert_eq!(read_register16(&cpu, DE), 0x00D8);
+    assert_eq!(read_register16(&cpu, HL), 0x014D);
+    assert_eq!(cpu.sp, Wrapping(0xFFFE));
+    assert_eq!(cpu.pc, Wrapping(0x0100));
+    assert_eq!(cpu.memory.read(0xFF40), 0x91);
+}
+
+#[test]
+fn post_boot_flags_with_zero_checksum() {
+    let cpu = load_cartridge(&[0; 0x8000], Model::Dmg, None).unwrap();
+    assert_eq!(cpu.flags, Flags::new(true, false, false, false));
+}
+
+#[test]
+fn post_boot_registers_by_model() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x014D] = 0x42;
+
+    let cpu = load_cartridge(&rom, Model::Dmg0, None).unwrap();
+    assert_eq!(cpu.a, Wrapping(0x01));
+    assert_eq!(read_register16(&cpu, BC), 0xFF13);
+    assert_eq!(read_register16(&cpu, HL), 0x8403);
+
+    let cpu = load_cartridge(&rom, Model::Mgb, None).unwrap();
+    assert_eq!(cpu.a, Wrapping(0xFF));
+    assert_eq!(cpu.flags, Flags::new(true, false, true, true));
+
+    let cpu = load_cartridge(&rom, Model::Sgb2, None).unwrap();
+    assert_eq!(cpu.a, Wrapping(0xFF));
+    assert_eq!(read_register16(&cpu, HL), 0xC060);
+
+    rom[0x0143] = 0x80;
+    let cpu = load_cartridge(&rom, Model::Cgb, None).unwrap();
+    assert_eq!(cpu.a, Wrapping(0x11));
+    assert_eq!(cpu.flags, Flags::new(true, false, false, false));
+    assert_eq!(read_register16(&cpu, DE), 0xFF56);
+
+    let cpu = load_cartridge(&rom, Model::Agb, None).unwrap();
+    assert_eq!(read_register16(&cpu, BC), 0x0100);
+    assert_eq!(cpu.flags, Flags::default());
+}
+
+#[test]
+fn cgb_running_dmg_game() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x0134] = 0x40;
+    rom[0x0135] = 0x02;
+
+    let cpu = load_cartridge(&rom, Model::Cgb, None).unwrap();
+    assert_eq!(read_register16(&cpu, BC), 0x0000);
+    assert_eq!(read_register16(&cpu, HL), 0x007C);
+
+    // Nintendo's licensee code.
+    rom[0x014B] = 0x01;
+    let cpu = load_cartridge(&rom, Model::Cgb, None).unwrap();
+    assert_eq!(cpu.b, Wrapping(0x42));
+    assert_eq!(read_register16(&cpu, HL), 0x991A);
+
+    let cpu = load_cartridge(&rom, Model::Agb, None).unwrap();
+    assert_eq!(cpu.b, Wrapping(0x43));
+}
+
+#[test]
+fn boot_rom_runs_first() {
+    let mut boot_rom = vec![0; 0x100];
+    // LD A,1; LDH ($50),A, then fall through to 0x0100.
+    boot_rom[0xFC..].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+    let program = [
+        0x3E, 0x2A, // LD A,42
+        0x10, 0x00, // STOP
+    ];
+    let mut rom = rom_with_program(&program);
+    rom[0x00] = 0xD3; // Illegal, but hidden by the boot ROM.
+
+    let cpu = load_cartridge(&rom, Model::Dmg, Some(&boot_rom)).unwrap();
+    assert_eq!(cpu.pc, Wrapping(0x0000));
+    assert_eq!(cpu.sp, Wrapping(0x0000));
+
+    let cpu = fetch_execute(&rom, Model::Dmg, Some(&boot_rom), None).unwrap();
+    assert_eq!(cpu.a, Wrapping(42));
+    assert_eq!(cpu.memory.read(0x0000), 0xD3);
+}
+
+#[test]
+fn timer_wakes_halt() {
+    let mut cpu = cpu_with_rom(&[
+        0x3E, 0x05, // LD A,$05
+        0xE0, 0x07, // LDH (TAC),A
+        0x3E, 0x04, // LD A,$04
+        0xE0, 0xFF, // LDH (IE),A
+        0x76,       // HALT
+        0x3E, 0x2A, // LD A,42
+        0x10, 0x00, // STOP
+    ]);
+    while !cpu.stopped && cpu.cycles < 10000 {
+        execute_next(&mut cpu).unwrap();
+    }
+
+    // TIMA overflows after 256 increments, one every four M-cycles.
+    assert_eq!(cpu.a, Wrapping(42));
+    assert!(cpu.cycles > 1024);
+    assert_eq!(cpu.memory.read(IF_ADDRESS), 0xE4);
+    // STOP resets DIV.
+    assert_eq!(cpu.memory.read(DIV_ADDRESS), 0x00);
+}
+
+#[test]
+fn joypad_wakes_halt() {
+    let mut cpu = cpu_with_rom(&[
+        0x3E, 0x10, // LD A,$10
+        0xE0, 0xFF, // LDH (IE),A
+        0xE0, 0x00, // LDH (P1),A ; select the action buttons
+        0x76,       // HALT
+        0xF0, 0x00, // LDH A,(P1)
+        0x10, 0x00, // STOP
+    ]);
+    for _ in 0..10 {
+        execute_next(&mut cpu).unwrap();
+    }
+    assert!(cpu.halted);
+
+    set_buttons(&mut cpu, Buttons { start: true, ..Buttons::default() });
+    while !cpu.stopped {
+        execute_next(&mut cpu).unwrap();
+    }
+    assert_eq!(cpu.a, Wrapping(0xD7));
+}
+
+#[test]
+fn serial_output_buffer() {
+    let program = [
+        0x3E, 0x48, // LD A,'H'
+        0xE0, 0x01, // LDH (SB),A
+        0x3E, 0x81, // LD A,$81
+        0xE0, 0x02, // LDH (SC),A
+        0xF0, 0x02, // wait: LDH A,(SC)
+        0x17,       // RLA
+        0x38, 0xFB, // JR C,wait
+        0x10, 0x00, // STOP
+    ];
+    let cpu = fetch_execute(&rom_with_program(&program), Model::Dmg, None, None).unwrap();
+    assert_eq!(serial_output(&cpu), b"H");
+}
+
+#[test]
+fn serial_output_after_hram_routine() {
+    // Like an OAM DMA routine, the code that loads SB runs from HRAM.
+    let program = [
+        0x31, 0xFE, 0xFF, // LD SP,$FFFE
+        0x21, 0x80, 0xFF, // LD HL,$FF80
+        0x36, 0x3E,       // LD (HL),$3E  ; LD A,'!'
+        0x23,             // INC HL
+        0x36, 0x21,       // LD (HL),$21
+        0x23,             // INC HL
+        0x36, 0xE0,       // LD (HL),$E0  ; LDH (SB),A
+        0x23,             // INC HL
+        0x36, 0x01,       // LD (HL),$01
+        0x23,             // INC HL
+        0x36, 0xC9,       // LD (HL),$C9  ; RET
+        0xCD, 0x80, 0xFF, // CALL $FF80
+        0x3E, 0x81,       // LD A,$81
+        0xE0, 0x02,       // LDH (SC),A
+        0xF0, 0x02,       // wait: LDH A,(SC)
+        0x17,             // RLA
+        0x38, 0xFB,       // JR C,wait
+        0x10, 0x00,       // STOP
+    ];
+    let cpu = fetch_execute(&rom_with_program(&program), Model::Dmg, None, None).unwrap();
+    assert_eq!(serial_output(&cpu), b"!");
+}
+
+#[test]
+fn fetch_execute_loop() {
+    let bytes = [
+        0x06, 0x03, // LD B,3
+        0x3E, 0x00, // LD A,0
+        0xC6, 0x02, // loop: ADD A,2
+        0x05,       // DEC B
+        0x20, 0xFB, // JR NZ,loop
+        0x10, 0x00, // STOP
+        0x3C,       // INC A, never reached
+    ];
+    let cpu = fetch_execute(&rom_with_program(&bytes), Model::Dmg, None, None).unwrap();
+
+    assert_eq!(cpu.a, Wrapping(6));
+    assert_eq!(cpu.b, Wrapping(0));
+    assert_eq!(cpu.pc, Wrapping(0x010B));
+    assert_eq!(cpu.memory.read(0x0104), 0xC6);
+}
+
+#[test]
+fn fetch_execute_call() {
+    let bytes = [
+        0x31, 0xFE, 0xFF, // LD SP,$FFFE
+        0xCD, 0x08, 0x01, // CALL $0108
+        0x10, 0x00,       // STOP
+        0x3E, 0x2A,       // LD A,42
+        0xC9,             // RET
+    ];
+    let cpu = fetch_execute(&rom_with_program(&bytes), Model::Dmg, None, None).unwrap();
+
+    assert_eq!(cpu.a, Wrapping(42));
+    assert_eq!(cpu.sp, Wrapping(0xFFFE));
+    assert_eq!(cpu.pc, Wrapping(0x0108));
+}
+
+#[test]
+fn fetch_execute_from_wram() {
+    let bytes = [
+        0x31, 0xFE, 0xFF, // LD SP,$FFFE
+        0x21, 0x00, 0xC0, // LD HL,$C000
+        0x36, 0x3E,       // LD (HL),$3E  ; LD A,42
+        0x23,             // INC HL
+        0x36, 0x2A,       // LD (HL),$2A
+        0x23,             // INC HL
+        0x36, 0xC9,       // LD (HL),$C9  ; RET
+        0xCD, 0x00, 0xC0, // CALL $C000
+        0x47,             // LD B,A
+        0x10, 0x00,       // STOP
+    ];
+    let cpu = fetch_execute(&rom_with_program(&bytes), Model::Dmg, None, None).unwrap();
+
+    assert_eq!(cpu.b, Wrapping(42));
+    assert_eq!(cpu.pc, Wrapping(0x0114));
+}
+
+#[test]
+fn run_cycle_limit() {
+    let bytes = [
+        0x18, 0xFE, // loop: JR loop
+    ];
+    let mut cpu = load_cartridge(&rom_with_program(&bytes), Model::Dmg, None).unwrap();
+    set_cycle_limit(&mut cpu, Some(1000));
+    run(&mut cpu, None).unwrap();
+
+    assert_eq!(cpu.pc, Wrapping(0x0100));
+    assert!(cpu.cycles >= 1000 && cpu.cycles < 1003);
+}
+
+#[test]
+fn fetch_execute_illegal_opcode() {
+    let bytes = [0x00, 0xD3];
+    assert!(fetch_execute(&rom_with_program(&bytes), Model::Dmg, None, None).is_err());
+}
+
+// Instruction timings in M-cycles, as (bytes, not taken, taken). For
+// instructions that don't branch, both timings are the same.
+#[test]
+fn instruction_timings() {
+    let timings: [(&[u8], u32, u32); 40] = [
+        (&[0x00], 1, 1),             // NOP
+        (&[0x01, 0x00, 0x00], 3, 3), // LD BC,d16
+        (&[0x02], 2, 2),             // LD (BC),A
+        (&[0x03], 2, 2),             // INC BC
+        (&[0x04], 1, 1),             // INC B
+        (&[0x06, 0x00], 2, 2),       // LD B,d8
+        (&[0x07], 1, 1),             // RLCA
+        (&[0x08, 0x00, 0x00], 5, 5), // LD (a16),SP
+        (&[0x09], 2, 2),             // ADD HL,BC
+        (&[0x18, 0x00], 3, 3),       // JR e8
+        (&[0x20, 0x00], 2, 3),       // JR NZ,e8
+        (&[0x22], 2, 2),             // LD (HL+),A
+        (&[0x34], 3, 3),             // INC (HL)
+        (&[0x36, 0x00], 3, 3),       // LD (HL),d8
+        (&[0x41], 1, 1),             // LD B,C
+        (&[0x46], 2, 2),             // LD B,(HL)
+        (&[0x70], 2, 2),             // LD (HL),B
+        (&[0x86], 2, 2),             // ADD A,(HL)
+        (&[0xAF], 1, 1),             // XOR A
+        (&[0xC0], 2, 5),             // RET NZ
+        (&[0xC1], 3, 3),             // POP BC
+        (&[0xC2, 0x00, 0x00], 3, 4), // JP NZ,a16
+        (&[0xC3, 0x00, 0x00], 4, 4), // JP a16
+        (&[0xC4, 0x00, 0x00], 3, 6), // CALL NZ,a16
+        (&[0xC5], 4, 4),             // PUSH BC
+        (&[0xC6, 0x00], 2, 2),       // ADD A,d8
+        (&[0xC7], 4, 4),             // RST 00H
+        (&[0xC9], 4, 4),             // RET
+        (&[0xCD, 0x00, 0x00], 6, 6), // CALL a16
+        (&[0xD9], 4, 4),             // RETI
+        (&[0xE0, 0x00], 3, 3),       // LDH (a8),A
+        (&[0xE2], 2, 2),             // LD (C),A
+        (&[0xE8, 0x00], 4, 4),       // ADD SP,e8
+        (&[0xE9], 1, 1),             // JP HL
+        (&[0xEA, 0x00, 0x00], 4, 4), // LD (a16),A
+        (&[0xF8, 0x00], 3, 3),       // LD HL,SP+e8
+        (&[0xF9], 2, 2),             // LD SP,HL
+        (&[0xCB, 0x06], 4, 4),       // RLC (HL)
+        (&[0xCB, 0x46], 3, 3),       // BIT 0,(HL)
+        (&[0xCB, 0xC0], 2, 2),       // SET 0,B
+    ];
+
+    for &(bytes, not_taken, taken) in timings.iter() {
+        let instr = decode(bytes, 0).unwrap();
+        assert_eq!(instr_cycles(&instr, false), not_taken, "{:?} not taken", instr);
+        assert_eq!(instr_cycles(&instr, true), taken, "{:?} taken", instr);
+    }
+}
+
+#[test]
+fn step_counts_cycles() {
+    let mut cpu = initial_cpu();
+    cpu.sp = Wrapping(0xFFFE);
+
+    step(&mut cpu, Nop).unwrap();
+    step(&mut cpu, Call(Condition::Always, 0x0100)).unwrap();
+    step(&mut cpu, Set(0, Operand8::MemoryAddress(HL))).unwrap();
+    assert_eq!(cpu.cycles, 1 + 6 + 4);
+}
+
+// A CPU with the given program as its ROM.
+#[cfg(test)]
+fn cpu_with_rom(program: &[u8]) -> CPU {
+    let mut cpu = initial_cpu();
+    cpu.memory = Memory::from_rom(program.to_vec()).unwrap();
+    cpu
+}
+
+#[test]
+fn interrupt_dispatch() {
+    let mut cpu = initial_cpu();
+    cpu.pc = Wrapping(0x0200);
+    cpu.sp = Wrapping(0xFFFE);
+    cpu.ime = true;
+    cpu.memory.write(IE_ADDRESS, 0x05);
+    cpu.memory.write(IF_ADDRESS, 0x06);
+
+    // Timer is the only interrupt that is both requested and enabled.
+    let cycles = execute_next(&mut cpu).unwrap();
+    assert_eq!(cycles, 5);
+    assert_eq!(cpu.pc, Wrapping(0x0050));
+    assert_eq!(cpu.memory.read(IF_ADDRESS), 0xE2);
+    assert!(!cpu.ime);
+
+    assert_eq!(cpu.sp, Wrapping(0xFFFC));
+    assert_eq!(cpu.memory.read(0xFFFD), 0x02);
+    assert_eq!(cpu.memory.read(0xFFFC), 0x00);
+}
+
+#[test]
+fn interrupt_not_dispatched_without_ime() {
+    let mut cpu = cpu_with_rom(&[0x00]);
+    cpu.memory.write(IE_ADDRESS, 0x01);
+    cpu.memory.write(IF_ADDRESS, 0x01);
+
+    let cycles = execute_next(&mut cpu).unwrap();
+    assert_eq!(cycles, 1);
+    assert_eq!(cpu.pc, Wrapping(0x0001));
+}
+
+#[test]
+fn ei_is_delayed() {
+    let mut cpu = cpu_with_rom(&[0xFB, 0x00, 0x00]); // EI; NOP; NOP
+    cpu.sp = Wrapping(0xFFFE);
+    cpu.memory.write(IE_ADDRESS, 0x01);
+    cpu.memory.write(IF_ADDRESS, 0x01);
+
+    execute_next(&mut cpu).unwrap();
+    assert!(!cpu.ime);
+
+    // The instruction after EI still runs before the interrupt.
+    execute_next(&mut cpu).unwrap();
+    assert!(cpu.ime);
+    assert_eq!(cpu.pc, Wrapping(0x0002));
+
+    execute_next(&mut cpu).unwrap();
+    assert_eq!(cpu.pc, Wrapping(0x0040));
+}
+
+#[test]
+fn di_after_ei() {
+    let mut cpu = cpu_with_rom(&[0xFB, 0xF3, 0x00]); // EI; DI; NOP
+
+    execute_next(&mut cpu).unwrap();
+    execute_next(&mut cpu).unwrap();
+    execute_next(&mut cpu).unwrap();
+    assert!(!cpu.ime);
+}
+
+#[test]
+fn halt_wakes_and_dispatches() {
+    let mut cpu = cpu_with_rom(&[0x76, 0x00]); // HALT; NOP
+    cpu.sp = Wrapping(0xFFFE);
+    cpu.ime = true;
+    cpu.memory.write(IE_ADDRESS, 0x01);
+
+    execute_next(&mut cpu).unwrap();
+    assert!(cpu.halted);
+
+    // Nothing requested yet, so we idle.
+    assert_eq!(execute_next(&mut cpu).unwrap(), 1);
+    assert_eq!(cpu.pc, Wrapping(0x0001));
+
+    cpu.memory.write(IF_ADDRESS, 0x01);
+    assert_eq!(execute_next(&mut cpu).unwrap(), 6);
+    assert!(!cpu.halted);
+    assert_eq!(cpu.pc, Wrapping(0x0040));
+}
+
+#[test]
+fn halt_wakes_without_ime() {
+    let mut cpu = cpu_with_rom(&[0x76, 0x3C]); // HALT; INC A
+    cpu.memory.write(IE_ADDRESS, 0x04);
+
+    execute_next(&mut cpu).unwrap();
+    assert!(cpu.halted);
+
+    // With IME clear, the interrupt ends HALT but isn't serviced.
+    cpu.memory.write(IF_ADDRESS, 0x04);
+    execute_next(&mut cpu).unwrap();
+    assert!(!cpu.halted);
+    assert_eq!(cpu.a, Wrapping(1));
+    assert_eq!(cpu.memory.read(IF_ADDRESS), 0xE4);
+}
+
+#[test]
+fn halt_bug() {
+    let mut cpu = cpu_with_rom(&[0x76, 0x3C, 0x00]); // HALT; INC A; NOP
+    cpu.memory.write(IE_ADDRESS, 0x01);
+    cpu.memory.write(IF_ADDRESS, 0x01);
+
+    execute_next(&mut cpu).unwrap();
+    assert!(!cpu.halted);
+
+    // INC A is executed twice.
+    execute_next(&mut cpu).unwrap();
+    assert_eq!(cpu.pc, Wrapping(0x0001));
+    execute_next(&mut cpu).unwrap();
+    assert_eq!(cpu.pc, Wrapping(0x0002));
+    assert_eq!(cpu.a, Wrapping(2));
+}
+
+#[test]
+fn halt_bug_operand() {
+    let mut cpu = cpu_with_rom(&[0x76, 0x3E, 0x14]); // HALT; LD A,$14
+    cpu.memory.write(IE_ADDRESS, 0x01);
+    cpu.memory.write(IF_ADDRESS, 0x01);
+
+    // The LD opcode is also read as its own operand.
+    execute_next(&mut cpu).unwrap();
+    execute_next(&mut cpu).unwrap();
+    assert_eq!(cpu.a, Wrapping(0x3E));
+    assert_eq!(cpu.pc, Wrapping(0x0002));
+}
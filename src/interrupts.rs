@@ -0,0 +1,59 @@
+/// Interrupt Enable register: which interrupts may be dispatched.
+pub const IE_ADDRESS: u16 = 0xFFFF;
+/// Interrupt Flag register: which interrupts have been requested.
+pub const IF_ADDRESS: u16 = 0xFF0F;
+
+/// Interrupt sources, in priority order.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Interrupt {
+    VBlank,
+    LcdStat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+impl Interrupt {
+    /// The bit for this interrupt in IE and IF.
+    pub fn bit(self) -> u8 {
+        match self {
+            Interrupt::VBlank => 0x01,
+            Interrupt::LcdStat => 0x02,
+            Interrupt::Timer => 0x04,
+            Interrupt::Serial => 0x08,
+            Interrupt::Joypad => 0x10,
+        }
+    }
+
+    /// The address the CPU jumps to when dispatching this interrupt.
+    pub fn vector(self) -> u16 {
+        match self {
+            Interrupt::VBlank => 0x40,
+            Interrupt::LcdStat => 0x48,
+            Interrupt::Timer => 0x50,
+            Interrupt::Serial => 0x58,
+            Interrupt::Joypad => 0x60,
+        }
+    }
+}
+
+/// Given the bits that are set in both IE and IF, return the
+/// interrupt that should be serviced first. Lower bits have priority.
+pub fn highest_priority(pending: u8) -> Option<Interrupt> {
+    let interrupts = [Interrupt::VBlank, Interrupt::LcdStat, Interrupt::Timer,
+                      Interrupt::Serial, Interrupt::Joypad];
+    interrupts.iter().cloned().find(|interrupt| pending & interrupt.bit() != 0)
+}
+
+#[test]
+fn priority_order() {
+    assert_eq!(highest_priority(0x00), None);
+    assert_eq!(highest_priority(0x1F), Some(Interrupt::VBlank));
+    assert_eq!(highest_priority(0x14), Some(Interrupt::Timer));
+    assert_eq!(highest_priority(0x10), Some(Interrupt::Joypad));
+}
+
+#[test]
+fn ignores_unused_bits() {
+    assert_eq!(highest_priority(0xE0), None);
+}
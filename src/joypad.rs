@@ -0,0 +1,149 @@
+/// P1/JOYP: button state, read through a select matrix.
+pub const P1_ADDRESS: u16 = 0xFF00;
+
+/// Writing 0 to these bits of P1 selects which buttons are read.
+const SELECT_DIRECTIONS: u8 = 0x10;
+const SELECT_ACTIONS: u8 = 0x20;
+
+/// Which buttons are held down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Buttons {
+    pub right: bool,
+    pub left: bool,
+    pub up: bool,
+    pub down: bool,
+    pub a: bool,
+    pub b: bool,
+    pub select: bool,
+    pub start: bool,
+}
+
+impl Buttons {
+    // Each group reads as a nibble, with pressed buttons as 1s.
+    fn directions(&self) -> u8 {
+        nibble(self.right, self.left, self.up, self.down)
+    }
+
+    fn actions(&self) -> u8 {
+        nibble(self.a, self.b, self.select, self.start)
+    }
+}
+
+fn nibble(bit0: bool, bit1: bool, bit2: bool, bit3: bool) -> u8 {
+    bit0 as u8 | (bit1 as u8) << 1 | (bit2 as u8) << 2 | (bit3 as u8) << 3
+}
+
+/// The joypad. The buttons are wired in a matrix: the game selects
+/// the direction keys, the action buttons, or both, and reads the
+/// selected buttons in the lower nibble of P1. Everything is active
+/// low, so a pressed button reads as 0.
+#[derive(Debug)]
+pub struct Joypad {
+    select: u8,
+    buttons: Buttons,
+}
+
+impl Default for Joypad {
+    fn default() -> Joypad {
+        Joypad::new()
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad {
+            select: SELECT_DIRECTIONS | SELECT_ACTIONS,
+            buttons: Buttons::default(),
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        // The top two bits are unused and read as 1.
+        0xC0 | self.select | (!self.pressed() & 0x0F)
+    }
+
+    /// Only the select bits can be written. Returns true if the
+    /// joypad interrupt should be requested.
+    pub fn write(&mut self, value: u8) -> bool {
+        let before = self.pressed();
+        self.select = value & (SELECT_DIRECTIONS | SELECT_ACTIONS);
+        self.interrupt(before)
+    }
+
+    /// Press and release buttons. Returns true if the joypad
+    /// interrupt should be requested.
+    pub fn set_buttons(&mut self, buttons: Buttons) -> bool {
+        let before = self.pressed();
+        self.buttons = buttons;
+        self.interrupt(before)
+    }
+
+    /// The selected buttons that are pressed, as 1s.
+    fn pressed(&self) -> u8 {
+        let mut pressed = 0;
+        if self.select & SELECT_DIRECTIONS == 0 {
+            pressed |= self.buttons.directions();
+        }
+        if self.select & SELECT_ACTIONS == 0 {
+            pressed |= self.buttons.actions();
+        }
+        pressed
+    }
+
+    /// The interrupt is requested when any line of the lower nibble
+    /// goes from high to low.
+    fn interrupt(&self, before: u8) -> bool {
+        self.pressed() & !before != 0
+    }
+}
+
+#[test]
+fn nothing_selected() {
+    let mut joypad = Joypad::new();
+    joypad.set_buttons(Buttons { a: true, down: true, ..Buttons::default() });
+    assert_eq!(joypad.read(), 0xFF);
+}
+
+#[test]
+fn select_directions() {
+    let mut joypad = Joypad::new();
+    joypad.set_buttons(Buttons { a: true, down: true, ..Buttons::default() });
+    joypad.write(0x20);
+    assert_eq!(joypad.read(), 0xE7);
+}
+
+#[test]
+fn select_actions() {
+    let mut joypad = Joypad::new();
+    joypad.set_buttons(Buttons { a: true, start: true, left: true, ..Buttons::default() });
+    joypad.write(0x10);
+    assert_eq!(joypad.read(), 0xD6);
+}
+
+#[test]
+fn select_both() {
+    let mut joypad = Joypad::new();
+    joypad.set_buttons(Buttons { a: true, left: true, ..Buttons::default() });
+    joypad.write(0x00);
+    assert_eq!(joypad.read(), 0xCC);
+}
+
+#[test]
+fn interrupt_on_press() {
+    let mut joypad = Joypad::new();
+    joypad.write(0x10);
+
+    // Not selected, so the line doesn't change.
+    assert!(!joypad.set_buttons(Buttons { up: true, ..Buttons::default() }));
+    assert!(joypad.set_buttons(Buttons { up: true, b: true, ..Buttons::default() }));
+    // Releasing is a low-to-high transition.
+    assert!(!joypad.set_buttons(Buttons::default()));
+}
+
+#[test]
+fn interrupt_on_select() {
+    let mut joypad = Joypad::new();
+    joypad.set_buttons(Buttons { up: true, ..Buttons::default() });
+    assert!(!joypad.write(0x10));
+    assert!(joypad.write(0x20));
+}
@@ -0,0 +1,13 @@
+pub mod cartridge;
+pub mod flags;
+pub mod instructions;
+pub mod interrupts;
+pub mod joypad;
+pub mod link;
+pub mod mbc;
+pub mod memory;
+pub mod model;
+pub mod ppu;
+pub mod save;
+pub mod serial;
+pub mod timer;
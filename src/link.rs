@@ -0,0 +1,210 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// The other end of a link cable.
+///
+/// Whichever Game Boy starts a transfer with the internal clock is
+/// the master for that byte. The master sends its byte and gets the
+/// slave's byte back. The slave's byte is whatever is in its SB when
+/// the master's clock arrives.
+pub trait Link {
+    /// As the master, swap `byte` for the slave's byte. Returns 0xFF
+    /// if nothing answers, as if the cable were unplugged.
+    fn exchange(&mut self, byte: u8) -> u8;
+
+    /// As the slave, check whether the master has clocked a byte in.
+    /// If so, `reply` is sent back and the master's byte is returned.
+    /// This must not block.
+    fn poll(&mut self, reply: u8) -> Option<u8>;
+}
+
+// Messages are two bytes: a kind, then the data byte.
+const TRANSFER: u8 = 0x01;
+const REPLY: u8 = 0x02;
+
+/// How long the master waits for the slave before giving up on a
+/// transfer.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A link cable to another emulator over TCP, usually on the same
+/// machine. The master blocks until the slave replies, which keeps
+/// the two emulators in step one byte at a time.
+pub struct TcpLink {
+    stream: TcpStream,
+    /// The start of a message whose second byte hasn't arrived.
+    partial: Option<u8>,
+}
+
+impl TcpLink {
+    /// Wait for another emulator to connect.
+    pub fn listen<A: ToSocketAddrs>(address: A) -> io::Result<TcpLink> {
+        let listener = TcpListener::bind(address)?;
+        let (stream, _) = listener.accept()?;
+        TcpLink::new(stream)
+    }
+
+    /// Connect to an emulator that's listening.
+    pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<TcpLink> {
+        TcpLink::new(TcpStream::connect(address)?)
+    }
+
+    fn new(stream: TcpStream) -> io::Result<TcpLink> {
+        // Each message is tiny and latency matters more than
+        // throughput.
+        stream.set_nodelay(true)?;
+        Ok(TcpLink {
+            stream,
+            partial: None,
+        })
+    }
+
+    fn send(&mut self, kind: u8, byte: u8) -> io::Result<()> {
+        self.stream.set_nonblocking(false)?;
+        self.stream.write_all(&[kind, byte])
+    }
+
+    /// Read a message. Returns None if `blocking` is false and no
+    /// complete message has arrived.
+    fn receive(&mut self, blocking: bool) -> io::Result<Option<(u8, u8)>> {
+        self.stream.set_nonblocking(!blocking)?;
+        self.stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+
+        loop {
+            let mut byte = [0];
+            match self.stream.read(&mut byte) {
+                Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "link closed")),
+                Ok(_) => match self.partial.take() {
+                    Some(kind) => return Ok(Some((kind, byte[0]))),
+                    None => self.partial = Some(byte[0]),
+                },
+                Err(ref e) if !blocking && e.kind() == ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Link for TcpLink {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        if self.send(TRANSFER, byte).is_err() {
+            return 0xFF;
+        }
+
+        loop {
+            match self.receive(true) {
+                Ok(Some((REPLY, reply))) => return reply,
+                // The other side started a transfer too. Two masters
+                // don't clock each other's data in.
+                Ok(Some((TRANSFER, _))) => {
+                    if self.send(REPLY, 0xFF).is_err() {
+                        return 0xFF;
+                    }
+                }
+                Ok(_) => {}
+                Err(_) => return 0xFF,
+            }
+        }
+    }
+
+    fn poll(&mut self, reply: u8) -> Option<u8> {
+        loop {
+            match self.receive(false) {
+                Ok(Some((TRANSFER, byte))) => {
+                    let _ = self.send(REPLY, reply);
+                    return Some(byte);
+                }
+                // A late reply to a transfer that timed out.
+                Ok(Some(_)) => {}
+                Ok(None) | Err(_) => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn connected_pair() -> (TcpLink, TcpLink) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let client = TcpLink::connect(address).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    (TcpLink::new(stream).unwrap(), client)
+}
+
+#[test]
+fn tcp_link_exchange() {
+    use std::thread;
+
+    let (mut master, slave) = connected_pair();
+    let slave_thread = thread::spawn(move || {
+        let mut slave = slave;
+        loop {
+            if let Some(byte) = slave.poll(0x99) {
+                return byte;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    });
+
+    assert_eq!(master.exchange(0x42), 0x99);
+    assert_eq!(slave_thread.join().unwrap(), 0x42);
+}
+
+#[test]
+fn tcp_link_poll_without_transfer() {
+    let (_, mut slave) = connected_pair();
+    assert_eq!(slave.poll(0x12), None);
+}
+
+#[test]
+fn tcp_link_disconnected() {
+    let (master, mut slave) = connected_pair();
+    drop(master);
+    assert_eq!(slave.exchange(0x12), 0xFF);
+}
+
+// Build a ROM with a program at the entry point.
+#[cfg(test)]
+fn link_test_rom(program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + program.len()].copy_from_slice(program);
+    rom
+}
+
+#[test]
+fn two_emulators() {
+    use std::thread;
+    use instructions::{load_cartridge, run, set_link, serial_output};
+    use model::Model;
+
+    // Each side sends a byte and waits for the transfer to finish. The
+    // master uses the internal clock, the slave the external clock.
+    let program = |sb: u8, sc: u8| link_test_rom(&[
+        0x3E, sb,   // LD A,sb
+        0xE0, 0x01, // LDH (SB),A
+        0x3E, sc,   // LD A,sc
+        0xE0, 0x02, // LDH (SC),A
+        0xF0, 0x02, // wait: LDH A,(SC)
+        0x17,       // RLA
+        0x38, 0xFB, // JR C,wait
+        0xF0, 0x01, // LDH A,(SB)
+        0x10, 0x00, // STOP
+    ]);
+    let master_rom = program(0x42, 0x81);
+    let slave_rom = program(0x99, 0x80);
+
+    let (master_link, slave_link) = connected_pair();
+    let slave = thread::spawn(move || {
+        let mut cpu = load_cartridge(&slave_rom, Model::Dmg, None).unwrap();
+        set_link(&mut cpu, Box::new(slave_link));
+        run(&mut cpu, None).unwrap();
+        serial_output(&cpu).to_vec()
+    });
+
+    let mut cpu = load_cartridge(&master_rom, Model::Dmg, None).unwrap();
+    set_link(&mut cpu, Box::new(master_link));
+    run(&mut cpu, None).unwrap();
+
+    assert_eq!(serial_output(&cpu), &[0x42]);
+    assert_eq!(slave.join().unwrap(), vec![0x99]);
+}
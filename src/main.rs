@@ -1,13 +1,20 @@
+use std::cmp::min;
 use std::env;
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read};
+use std::path::Path;
 
-mod instructions;
+extern crate gameboy_emulator;
 
-use instructions::*;
+use gameboy_emulator::cartridge::parse_header;
+use gameboy_emulator::instructions::*;
+use gameboy_emulator::link::TcpLink;
+use gameboy_emulator::model::Model;
+use gameboy_emulator::save::{save_path, SaveFile};
+use gameboy_emulator::serial::SerialOutput;
 
 fn read_bytes(path: &str) -> Result<Vec<u8>, std::io::Error> {
-    let mut file = try!(File::open(path));
+    let mut file = File::open(path)?;
     let mut bytes = vec![];
     let _ = file.read_to_end(&mut bytes);
     Ok(bytes)
@@ -19,7 +26,9 @@ fn print_instrs(bytes: &[u8]) {
     let mut offset = 0;
     while offset < bytes.len() {
         let instr = decode(bytes, offset);
-        let byte_count = instr.as_ref().map_or(1, instr_size);
+        // A truncated instruction at the end decodes as None, but
+        // clamp anyway so we never read past the end.
+        let byte_count = min(instr.as_ref().map_or(1, instr_size), bytes.len() - offset);
 
         // Build up a string of bytes for this instr e.g. "FF 00"
         let mut bytes_repr = format!("{:02X}", bytes[offset]);
@@ -39,37 +48,148 @@ fn print_instrs(bytes: &[u8]) {
     }
 }
 
+/// Describe a cartridge header, one field per line. Fields with codes
+/// we don't recognise are shown as unknown, so odd dumps can still be
+/// triaged.
+fn cartridge_info(bytes: &[u8]) -> Result<Vec<String>, String> {
+    let header = parse_header(bytes)?;
+
+    let validity = |valid| if valid { "valid" } else { "INVALID" };
+    let size = |size: Option<usize>, code: u8| match size {
+        Some(size) => format!("{} KiB", size / 1024),
+        None => format!("unknown (${:02X})", code),
+    };
+    let cartridge_type = match header.cartridge_type {
+        Some(cartridge_type) => format!("${:02X} {}", cartridge_type.code, cartridge_type),
+        None => format!("unknown (${:02X})", header.cartridge_type_code),
+    };
+
+    let mut lines = vec![
+        format!("Title:            {}", header.title),
+        format!("Manufacturer:     {}", header.manufacturer_code),
+        format!("Licensee:         {}", header.licensee_code),
+        format!("CGB:              {:?}", header.cgb),
+        format!("SGB:              {}", if header.sgb { "yes" } else { "no" }),
+        format!("Cartridge type:   {}", cartridge_type),
+        format!("ROM size:         {}", size(header.rom_size, header.rom_size_code)),
+        format!("RAM size:         {}", size(header.ram_size, header.ram_size_code)),
+        format!("Destination:      {:?}", header.destination),
+        format!("Version:          {}", header.version),
+        format!("Header checksum:  ${:02X} ({})",
+                header.header_checksum, validity(header.header_checksum_valid())),
+        format!("Global checksum:  ${:04X} ({})",
+                header.global_checksum, validity(header.global_checksum_valid())),
+    ];
+
+    if let Some(rom_size) = header.rom_size {
+        if bytes.len() != rom_size {
+            lines.push(format!("Warning: file is {} bytes, but the header says {} bytes",
+                               bytes.len(), rom_size));
+        }
+    }
+
+    Ok(lines)
+}
+
+/// Run a ROM until it stops, or for a given number of M-cycles.
+/// Anything the game sends over the serial port is written to stdout.
+fn run_rom(path: &str, options: &[String]) -> Result<(), String> {
+    let mut boot_rom = None;
+    let mut model = None;
+    let mut trace = false;
+    let mut cycle_limit = None;
+    let mut link = None;
+
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        if option == "--trace" {
+            trace = true;
+            continue;
+        }
+
+        let value = match options.next() {
+            Some(value) => value,
+            None => return Err(format!("Missing value for {}", option)),
+        };
+
+        if option == "--boot-rom" {
+            boot_rom = Some(read_bytes(value)
+                .map_err(|_| format!("Could not read boot ROM: {}", value))?);
+        } else if option == "--cycles" {
+            cycle_limit = Some(value.parse()
+                .map_err(|_| format!("Invalid cycle count: {}", value))?);
+        } else if option == "--link-listen" {
+            println!("Waiting for a link cable connection on {}", value);
+            link = Some(TcpLink::listen(value.as_str())
+                .map_err(|e| format!("Could not listen on {}: {}", value, e))?);
+        } else if option == "--link-connect" {
+            link = Some(TcpLink::connect(value.as_str())
+                .map_err(|e| format!("Could not connect to {}: {}", value, e))?);
+        } else if option == "--model" {
+            model = Some(Model::from_name(value)
+                .ok_or_else(|| format!("Unknown model: {}", value))?);
+        } else {
+            return Err(format!("Unknown option {}", option));
+        }
+    }
+
+    let bytes = read_bytes(path).map_err(|_| format!("Could not read file: {}", path))?;
+    let model = model.unwrap_or_else(|| Model::detect(&bytes));
+
+    let mut cpu = load_cartridge(&bytes[..], model, boot_rom.as_ref().map(|b| &b[..]))?;
+    set_trace(&mut cpu, trace);
+    set_cycle_limit(&mut cpu, cycle_limit);
+    set_serial_output(&mut cpu, SerialOutput::Stream(Box::new(io::stdout())));
+    if let Some(link) = link {
+        set_link(&mut cpu, Box::new(link));
+    }
+
+    let mut save_file = SaveFile::new(save_path(Path::new(path)));
+    run(&mut cpu, Some(&mut save_file))?;
+
+    println!();
+    println!("Final CPU state: {:?}", cpu);
+    println!("Execution terminated normally.");
+    Ok(())
+}
+
 fn print_opcodes_implemented() {
     let mut implemented = 0;
     let mut total = 0;
     for byte1 in 0..256u16 {
-        
-        if byte1 == 0xCB {
-            for byte2 in 0..256u16 {
-                let example_sequence = [byte1 as u8, byte2 as u8, 0, 0];
+        if byte1 == 0xCB || ILLEGAL_OPCODES.contains(&(byte1 as u8)) {
+            continue;
+        }
 
-                if decode(&example_sequence, 0).is_some() {
-                    implemented += 1;
-                }
+        let example_sequence = [byte1 as u8, 0, 0];
 
-                total += 1;
-            }
-        } else {
-            let example_sequence = [byte1 as u8, 0, 0];
+        if decode(&example_sequence, 0).is_some() {
+            implemented += 1;
+        }
 
-            if decode(&example_sequence, 0).is_some() {
-                implemented += 1;
-            }
-            
-            total += 1;
+        total += 1;
+    }
+    print_percentage("base", implemented, total);
+
+    let mut implemented = 0;
+    let mut total = 0;
+    for byte2 in 0..256u16 {
+        let example_sequence = [0xCB, byte2 as u8, 0, 0];
+
+        if decode(&example_sequence, 0).is_some() {
+            implemented += 1;
         }
+
+        total += 1;
     }
+    print_percentage("0xCB prefixed", implemented, total);
+}
 
-    println!("Implemented {} of {} instructions ({:.1}%)",
-             implemented, total, 100.0 * implemented as f64 / total as f64);
+fn print_percentage(table: &str, implemented: usize, total: usize) {
+    println!("Implemented {} of {} {} instructions ({:.1}%)",
+             implemented, total, table, 100.0 * implemented as f64 / total as f64);
 }
 
-#[cfg_attr(test, allow(dead_code))]
 fn main() {
     let args: Vec<_> = env::args().collect();
 
@@ -106,17 +226,19 @@ fn main() {
                     std::process::exit(1);
                 }
             }
-        } else if command == "--run" {
-            // Read a file and execute it.
+        } else if command == "--info" {
+            // Read a file and print its cartridge header.
             match read_bytes(path) {
                 Ok(bytes) => {
-                    match fetch_execute(&bytes[..]) {
-                        Ok(_) => {
-                            println!("Execution terminatd normally.");
+                    match cartridge_info(&bytes[..]) {
+                        Ok(lines) => {
+                            for line in lines {
+                                println!("{}", line);
+                            }
                             return;
                         }
                         Err(msg) => {
-                            println!("Failed: {}", msg);
+                            println!("Invalid cartridge header: {}", msg);
                             std::process::exit(1);
                         }
                     }
@@ -129,9 +251,41 @@ fn main() {
         }
     }
 
+    if args.len() >= 3 && args[1] == "--run" {
+        // Read a file and execute it.
+        match run_rom(&args[2], &args[3..]) {
+            Ok(()) => return,
+            Err(msg) => {
+                println!("Failed: {}", msg);
+                std::process::exit(1);
+            }
+        }
+    }
+
     println!("Usage:");
     println!("{} /path/to/rom # disassemble", args[0]);
+    println!("{} --info /path/to/rom # show cartridge header", args[0]);
+    println!("{} --run /path/to/rom [--boot-rom /path/to/boot.bin] [--model dmg] [--cycles N] [--trace] # execute",
+             args[0]);
+    println!("    [--link-listen 127.0.0.1:8765 | --link-connect 127.0.0.1:8765] # link cable");
     println!("{} --implemented # count opcodes we understand", args[0]);
     println!("{} --demo # exercise the emulator", args[0]);
     std::process::exit(1);
 }
+
+#[test]
+fn info_unknown_cartridge_type() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x134..0x139].copy_from_slice(b"DUMP!");
+    rom[0x147] = 0x04;
+    rom[0x148] = 0x52;
+    rom[0x149] = 0x07;
+
+    let lines = cartridge_info(&rom).unwrap();
+    assert!(lines.contains(&"Title:            DUMP!".to_owned()));
+    assert!(lines.contains(&"Cartridge type:   unknown ($04)".to_owned()));
+    assert!(lines.contains(&"ROM size:         unknown ($52)".to_owned()));
+    assert!(lines.contains(&"RAM size:         unknown ($07)".to_owned()));
+    assert!(lines.contains(&"Header checksum:  $00 (INVALID)".to_owned()));
+    assert!(lines.contains(&"Global checksum:  $0000 (INVALID)".to_owned()));
+}
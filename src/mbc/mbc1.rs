@@ -0,0 +1,274 @@
+use super::{bank_mask, battery_ram, load_ram, read_bank, Mbc, RAM_BANK_SIZE, ROM_BANK_SIZE};
+#[cfg(test)]
+use super::banked_rom;
+
+/// MBC1, which supports up to 2 MiB of ROM and 32 KiB of RAM.
+///
+/// There are two bank registers. BANK1 is five bits and holds the
+/// lower bits of the ROM bank at 4000-7FFF. BANK2 is two bits and
+/// holds either the upper bits of the ROM bank, or the RAM bank. In
+/// mode 1 BANK2 also applies to 0000-3FFF and to RAM, so large ROMs
+/// can reach banks $20, $40 and $60 at 0000-3FFF.
+pub struct Mbc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    bank1: u8,
+    bank2: u8,
+    mode: bool,
+    /// MBC1M multicarts only connect four bits of BANK1, so BANK2
+    /// selects one of four 256 KiB games.
+    multicart: bool,
+    battery: bool,
+}
+
+impl Mbc1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, battery: bool) -> Mbc1 {
+        let multicart = is_multicart(&rom);
+        Mbc1 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            mode: false,
+            multicart,
+            battery,
+        }
+    }
+
+    fn bank2_shift(&self) -> u8 {
+        if self.multicart { 4 } else { 5 }
+    }
+
+    fn rom_bank_low(&self) -> usize {
+        let bank = if self.mode {
+            (self.bank2 << self.bank2_shift()) as usize
+        } else {
+            0
+        };
+        bank & bank_mask(self.rom.len(), ROM_BANK_SIZE)
+    }
+
+    fn rom_bank_high(&self) -> usize {
+        let bank1 = if self.multicart { self.bank1 & 0x0F } else { self.bank1 };
+        let bank = (self.bank2 << self.bank2_shift()) | bank1;
+        bank as usize & bank_mask(self.rom.len(), ROM_BANK_SIZE)
+    }
+
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return None;
+        }
+        let bank = if self.mode { self.bank2 as usize } else { 0 };
+        let bank = bank & bank_mask(self.ram.len(), RAM_BANK_SIZE);
+        // 2 KiB RAM chips are mirrored across the bank.
+        let offset = (address as usize - 0xA000) % self.ram.len().min(RAM_BANK_SIZE);
+        Some(bank * RAM_BANK_SIZE + offset)
+    }
+}
+
+impl Mbc for Mbc1 {
+    fn rom_size(&self) -> usize {
+        self.rom.len()
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        let bank = if address < 0x4000 {
+            self.rom_bank_low()
+        } else {
+            self.rom_bank_high()
+        };
+        read_bank(&self.rom, bank, ROM_BANK_SIZE, address as usize)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            // Bank 0 can't be selected here: writing 0 selects bank 1.
+            // The check is on all five bits, so $20 selects $21 and so on.
+            0x2000..=0x3FFF => self.bank1 = match value & 0x1F {
+                0 => 1,
+                bank => bank,
+            },
+            0x4000..=0x5FFF => self.bank2 = value & 0x03,
+            _ => self.mode = value & 0x01 != 0,
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        match self.ram_offset(address) {
+            Some(offset) => self.ram[offset],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if let Some(offset) = self.ram_offset(address) {
+            self.ram[offset] = value;
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        battery_ram(self.battery, &self.ram)
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+}
+
+/// MBC1M carts use the same cartridge type as MBC1, so they can only
+/// be spotted by their contents: they're 1 MiB and each 256 KiB game
+/// has its own header with the boot logo.
+fn is_multicart(rom: &[u8]) -> bool {
+    const LOGO: usize = 0x0104;
+    const LOGO_END: usize = 0x0134;
+    const GAME_SIZE: usize = 0x10 * ROM_BANK_SIZE;
+
+    if rom.len() != 4 * GAME_SIZE {
+        return false;
+    }
+    let logo = &rom[LOGO..LOGO_END];
+    if logo.iter().all(|b| *b == 0x00) || logo.iter().all(|b| *b == 0xFF) {
+        return false;
+    }
+    let copies = (1..4)
+        .filter(|game| &rom[game * GAME_SIZE + LOGO..game * GAME_SIZE + LOGO_END] == logo)
+        .count();
+    copies >= 2
+}
+
+#[cfg(test)]
+fn multicart_rom() -> Vec<u8> {
+    let mut rom = banked_rom(64);
+    for game in 0..4 {
+        let start = game * 0x10 * ROM_BANK_SIZE + 0x104;
+        for (i, b) in rom[start..start + 0x30].iter_mut().enumerate() {
+            *b = i as u8 + 1;
+        }
+    }
+    rom
+}
+
+#[test]
+fn mbc1_rom_banks() {
+    let mut mbc = Mbc1::new(banked_rom(8), 0, false);
+    assert_eq!(mbc.read_rom(0x0000), 0);
+    assert_eq!(mbc.read_rom(0x4000), 1);
+
+    mbc.write_rom(0x2000, 0x05);
+    assert_eq!(mbc.read_rom(0x4000), 5);
+    assert_eq!(mbc.read_rom(0x0000), 0);
+}
+
+#[test]
+fn mbc1_bank_zero_selects_bank_one() {
+    let mut mbc = Mbc1::new(banked_rom(8), 0, false);
+    mbc.write_rom(0x2000, 0x00);
+    assert_eq!(mbc.read_rom(0x4000), 1);
+
+    // Only the low five bits are compared against zero.
+    mbc.write_rom(0x2000, 0xE0);
+    assert_eq!(mbc.read_rom(0x4000), 1);
+}
+
+#[test]
+fn mbc1_bank_number_masked_to_rom_size() {
+    let mut mbc = Mbc1::new(banked_rom(4), 0, false);
+    mbc.write_rom(0x2000, 0x06);
+    assert_eq!(mbc.read_rom(0x4000), 2);
+
+    // Bank $04 wraps to bank 0, which the bank-1 check doesn't catch.
+    mbc.write_rom(0x2000, 0x04);
+    assert_eq!(mbc.read_rom(0x4000), 0);
+}
+
+#[test]
+fn mbc1_upper_bank_bits() {
+    let mut mbc = Mbc1::new(banked_rom(128), 0, false);
+    mbc.write_rom(0x4000, 0x02);
+    mbc.write_rom(0x2000, 0x03);
+    assert_eq!(mbc.read_rom(0x4000), 0x43);
+
+    // $40 can't be selected directly, it becomes $41.
+    mbc.write_rom(0x2000, 0x00);
+    assert_eq!(mbc.read_rom(0x4000), 0x41);
+}
+
+#[test]
+fn mbc1_mode_one_banks_low_rom() {
+    let mut mbc = Mbc1::new(banked_rom(128), 0, false);
+    mbc.write_rom(0x4000, 0x03);
+    assert_eq!(mbc.read_rom(0x0000), 0x00);
+
+    mbc.write_rom(0x6000, 0x01);
+    assert_eq!(mbc.read_rom(0x0000), 0x60);
+    assert_eq!(mbc.read_rom(0x4000), 0x61);
+}
+
+#[test]
+fn mbc1_ram_enable() {
+    let mut mbc = Mbc1::new(banked_rom(2), 0x2000, false);
+    mbc.write_ram(0xA000, 0x42);
+    assert_eq!(mbc.read_ram(0xA000), 0xFF);
+
+    mbc.write_rom(0x0000, 0x0A);
+    mbc.write_ram(0xA000, 0x42);
+    assert_eq!(mbc.read_ram(0xA000), 0x42);
+
+    // Any value without $A in the low nibble disables RAM.
+    mbc.write_rom(0x1FFF, 0x1B);
+    assert_eq!(mbc.read_ram(0xA000), 0xFF);
+    mbc.write_rom(0x0000, 0xFA);
+    assert_eq!(mbc.read_ram(0xA000), 0x42);
+}
+
+#[test]
+fn mbc1_ram_banks() {
+    let mut mbc = Mbc1::new(banked_rom(2), 0x8000, false);
+    mbc.write_rom(0x0000, 0x0A);
+    mbc.write_ram(0xA000, 0x11);
+
+    // In mode 0 BANK2 doesn't affect RAM.
+    mbc.write_rom(0x4000, 0x02);
+    assert_eq!(mbc.read_ram(0xA000), 0x11);
+
+    mbc.write_rom(0x6000, 0x01);
+    assert_eq!(mbc.read_ram(0xA000), 0x00);
+    mbc.write_ram(0xA000, 0x22);
+
+    mbc.write_rom(0x4000, 0x00);
+    assert_eq!(mbc.read_ram(0xA000), 0x11);
+    mbc.write_rom(0x4000, 0x02);
+    assert_eq!(mbc.read_ram(0xA000), 0x22);
+}
+
+#[test]
+fn mbc1_small_ram_is_mirrored() {
+    let mut mbc = Mbc1::new(banked_rom(2), 0x800, false);
+    mbc.write_rom(0x0000, 0x0A);
+    mbc.write_ram(0xA001, 0x42);
+    assert_eq!(mbc.read_ram(0xA801), 0x42);
+}
+
+#[test]
+fn mbc1_multicart() {
+    let mut mbc = Mbc1::new(multicart_rom(), 0, false);
+    assert!(mbc.multicart);
+
+    // BANK2 selects the game, BANK1 only has four bits.
+    mbc.write_rom(0x4000, 0x01);
+    mbc.write_rom(0x2000, 0x12);
+    assert_eq!(mbc.read_rom(0x4000), 0x12);
+
+    mbc.write_rom(0x6000, 0x01);
+    mbc.write_rom(0x4000, 0x03);
+    assert_eq!(mbc.read_rom(0x0000), 0x30);
+}
+
+#[test]
+fn mbc1_large_rom_is_not_multicart() {
+    assert!(!is_multicart(&banked_rom(64)));
+    assert!(!is_multicart(&banked_rom(128)));
+}
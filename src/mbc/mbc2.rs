@@ -0,0 +1,175 @@
+use super::{bank_mask, battery_ram, load_ram, read_bank, Mbc, ROM_BANK_SIZE};
+#[cfg(test)]
+use super::banked_rom;
+
+/// MBC2 has 512 half-byte cells of RAM on the chip itself.
+const RAM_SIZE: usize = 0x200;
+
+/// MBC2, which supports up to 256 KiB of ROM and has its own 512x4
+/// bit RAM.
+///
+/// Both registers live in 0000-3FFF, and address bit 8 selects
+/// between them: clear for RAM enable, set for the ROM bank.
+pub struct Mbc2 {
+    rom: Vec<u8>,
+    /// Only the lower nibble of each cell is stored.
+    ram: [u8; RAM_SIZE],
+    ram_enabled: bool,
+    rom_bank: u8,
+    battery: bool,
+}
+
+impl Mbc2 {
+    pub fn new(rom: Vec<u8>, battery: bool) -> Mbc2 {
+        Mbc2 {
+            rom,
+            ram: [0; RAM_SIZE],
+            ram_enabled: false,
+            rom_bank: 1,
+            battery,
+        }
+    }
+
+    /// Only nine address lines reach the RAM, so it's echoed across
+    /// A000-BFFF.
+    fn ram_offset(address: u16) -> usize {
+        address as usize % RAM_SIZE
+    }
+}
+
+impl Mbc for Mbc2 {
+    fn rom_size(&self) -> usize {
+        self.rom.len()
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        let bank = if address < 0x4000 {
+            0
+        } else {
+            self.rom_bank as usize & bank_mask(self.rom.len(), ROM_BANK_SIZE)
+        };
+        read_bank(&self.rom, bank, ROM_BANK_SIZE, address as usize)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        if address >= 0x4000 {
+            return;
+        }
+
+        if address & 0x0100 == 0 {
+            self.ram_enabled = value & 0x0F == 0x0A;
+        } else {
+            // As with MBC1, bank 0 selects bank 1.
+            self.rom_bank = match value & 0x0F {
+                0 => 1,
+                bank => bank,
+            };
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        // The upper nibble isn't connected, so it reads as 1s.
+        self.ram[Mbc2::ram_offset(address)] | 0xF0
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.ram_enabled {
+            self.ram[Mbc2::ram_offset(address)] = value & 0x0F;
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        battery_ram(self.battery, &self.ram)
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+        for cell in self.ram.iter_mut() {
+            *cell &= 0x0F;
+        }
+    }
+}
+
+#[test]
+fn mbc2_rom_bank_needs_address_bit_8() {
+    let mut mbc = Mbc2::new(banked_rom(16), false);
+    mbc.write_rom(0x2000, 0x05);
+    assert_eq!(mbc.read_rom(0x4000), 1);
+
+    mbc.write_rom(0x2100, 0x05);
+    assert_eq!(mbc.read_rom(0x4000), 5);
+
+    // Bit 8 is all that matters, not which half of 0000-3FFF.
+    mbc.write_rom(0x0100, 0xF7);
+    assert_eq!(mbc.read_rom(0x4000), 7);
+    assert_eq!(mbc.read_rom(0x0000), 0);
+}
+
+#[test]
+fn mbc2_bank_zero_selects_bank_one() {
+    let mut mbc = Mbc2::new(banked_rom(16), false);
+    mbc.write_rom(0x2100, 0x10);
+    assert_eq!(mbc.read_rom(0x4000), 1);
+}
+
+#[test]
+fn mbc2_writes_above_3fff_ignored() {
+    let mut mbc = Mbc2::new(banked_rom(16), false);
+    mbc.write_rom(0x4100, 0x05);
+    assert_eq!(mbc.read_rom(0x4000), 1);
+}
+
+#[test]
+fn mbc2_ram_enable() {
+    let mut mbc = Mbc2::new(banked_rom(2), false);
+    mbc.write_ram(0xA000, 0x05);
+    assert_eq!(mbc.read_ram(0xA000), 0xFF);
+
+    // RAM enable needs bit 8 clear.
+    mbc.write_rom(0x0100, 0x0A);
+    assert_eq!(mbc.read_ram(0xA000), 0xFF);
+
+    mbc.write_rom(0x1000, 0x0A);
+    mbc.write_ram(0xA000, 0x05);
+    assert_eq!(mbc.read_ram(0xA000), 0xF5);
+}
+
+#[test]
+fn mbc2_ram_is_four_bits() {
+    let mut mbc = Mbc2::new(banked_rom(2), false);
+    mbc.write_rom(0x0000, 0x0A);
+    mbc.write_ram(0xA123, 0xAB);
+    assert_eq!(mbc.read_ram(0xA123), 0xFB);
+}
+
+#[test]
+fn mbc2_ram_echoes() {
+    let mut mbc = Mbc2::new(banked_rom(2), false);
+    mbc.write_rom(0x0000, 0x0A);
+    mbc.write_ram(0xA042, 0x03);
+    assert_eq!(mbc.read_ram(0xA242), 0xF3);
+    assert_eq!(mbc.read_ram(0xBE42), 0xF3);
+}
+
+#[test]
+fn mbc2_battery() {
+    let mut mbc = Mbc2::new(banked_rom(2), true);
+    mbc.write_rom(0x0000, 0x0A);
+    mbc.write_ram(0xA001, 0x0C);
+
+    let save = mbc.save_data().unwrap();
+    assert_eq!(save.len(), RAM_SIZE);
+    assert_eq!(save[1], 0x0C);
+
+    let mut restored = Mbc2::new(banked_rom(2), true);
+    restored.load_save_data(&[0xFF, 0x0C]);
+    restored.write_rom(0x0000, 0x0A);
+    assert_eq!(restored.read_ram(0xA000), 0xFF);
+    assert_eq!(restored.read_ram(0xA001), 0xFC);
+    assert_eq!(restored.ram[0], 0x0F);
+
+    assert_eq!(Mbc2::new(banked_rom(2), false).save_data(), None);
+}
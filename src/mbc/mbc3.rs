@@ -0,0 +1,253 @@
+use super::rtc::{Rtc, TimeSource, FOOTER_SIZE};
+use super::{bank_mask, battery_ram, load_ram, read_bank, Mbc, RAM_BANK_SIZE, ROM_BANK_SIZE};
+#[cfg(test)]
+use super::banked_rom;
+#[cfg(test)]
+use super::rtc::ManualClock;
+
+/// MBC3, which supports up to 2 MiB of ROM, 32 KiB of RAM and
+/// optionally a real time clock.
+///
+/// 4000-5FFF selects what's mapped at A000-BFFF: 00-03 for a RAM bank
+/// or 08-0C for one of the clock registers.
+pub struct Mbc3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    /// Enables both RAM and the clock.
+    ram_enabled: bool,
+    rom_bank: u8,
+    ram_select: u8,
+    rtc: Option<Rtc>,
+    battery: bool,
+}
+
+impl Mbc3 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, battery: bool) -> Mbc3 {
+        Mbc3 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_select: 0,
+            rtc: None,
+            battery,
+        }
+    }
+
+    /// An MBC3 with a clock, which gets the time from `clock`.
+    pub fn with_rtc(rom: Vec<u8>, ram_size: usize, battery: bool,
+                    clock: Box<dyn TimeSource>) -> Mbc3 {
+        let mut mbc = Mbc3::new(rom, ram_size, battery);
+        mbc.rtc = Some(Rtc::new(clock));
+        mbc
+    }
+
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+        let bank = self.ram_select as usize & bank_mask(self.ram.len(), RAM_BANK_SIZE);
+        let offset = (address as usize - 0xA000) % self.ram.len().min(RAM_BANK_SIZE);
+        Some(bank * RAM_BANK_SIZE + offset)
+    }
+
+    fn rtc_selected(&self) -> bool {
+        (0x08..=0x0C).contains(&self.ram_select)
+    }
+}
+
+impl Mbc for Mbc3 {
+    fn rom_size(&self) -> usize {
+        self.rom.len()
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        let bank = if address < 0x4000 {
+            0
+        } else {
+            self.rom_bank as usize & bank_mask(self.rom.len(), ROM_BANK_SIZE)
+        };
+        read_bank(&self.rom, bank, ROM_BANK_SIZE, address as usize)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            // Unlike MBC1, only bank 0 itself is remapped to bank 1.
+            0x2000..=0x3FFF => self.rom_bank = match value & 0x7F {
+                0 => 1,
+                bank => bank,
+            },
+            0x4000..=0x5FFF => self.ram_select = value & 0x0F,
+            _ => {
+                if let Some(ref mut rtc) = self.rtc {
+                    rtc.write_latch(value);
+                }
+            }
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        if self.rtc_selected() {
+            return match self.rtc {
+                Some(ref rtc) => rtc.read(self.ram_select),
+                None => 0xFF,
+            };
+        }
+        match self.ram_offset(address) {
+            Some(offset) if self.ram_select < 0x08 => self.ram[offset],
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        if self.rtc_selected() {
+            if let Some(ref mut rtc) = self.rtc {
+                rtc.write(self.ram_select, value);
+            }
+            return;
+        }
+        if self.ram_select < 0x08 {
+            if let Some(offset) = self.ram_offset(address) {
+                self.ram[offset] = value;
+            }
+        }
+    }
+
+    /// Save RAM, followed by the clock state if there is a clock.
+    fn save_data(&self) -> Option<Vec<u8>> {
+        if !self.battery {
+            return None;
+        }
+        match self.rtc {
+            Some(ref rtc) => {
+                let mut data = self.ram.clone();
+                data.extend(rtc.footer());
+                Some(data)
+            }
+            None => battery_ram(self.battery, &self.ram),
+        }
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+        if let Some(ref mut rtc) = self.rtc {
+            if data.len() >= self.ram.len() + FOOTER_SIZE {
+                rtc.load_footer(&data[self.ram.len()..]);
+            }
+        }
+    }
+}
+
+#[test]
+fn mbc3_rom_banks() {
+    let mut mbc = Mbc3::new(banked_rom(128), 0, false);
+    mbc.write_rom(0x2000, 0x00);
+    assert_eq!(mbc.read_rom(0x4000), 0x01);
+
+    // All seven bits are used, so $20 isn't remapped.
+    mbc.write_rom(0x2000, 0x20);
+    assert_eq!(mbc.read_rom(0x4000), 0x20);
+
+    mbc.write_rom(0x2000, 0xFF);
+    assert_eq!(mbc.read_rom(0x4000), 0x7F);
+    assert_eq!(mbc.read_rom(0x0000), 0x00);
+}
+
+#[test]
+fn mbc3_ram_banks() {
+    let mut mbc = Mbc3::new(banked_rom(2), 0x8000, false);
+    mbc.write_ram(0xA000, 0x11);
+    assert_eq!(mbc.read_ram(0xA000), 0xFF);
+
+    mbc.write_rom(0x0000, 0x0A);
+    mbc.write_ram(0xA000, 0x11);
+    mbc.write_rom(0x4000, 0x03);
+    mbc.write_ram(0xA000, 0x33);
+
+    assert_eq!(mbc.read_ram(0xA000), 0x33);
+    mbc.write_rom(0x4000, 0x00);
+    assert_eq!(mbc.read_ram(0xA000), 0x11);
+}
+
+#[test]
+fn mbc3_rtc_registers() {
+    let clock = ManualClock::new(0);
+    let mut mbc = Mbc3::with_rtc(banked_rom(2), 0x2000, true, Box::new(clock.clone()));
+    mbc.write_rom(0x0000, 0x0A);
+    mbc.write_ram(0xA000, 0x42);
+
+    clock.advance(61);
+    mbc.write_rom(0x6000, 0x00);
+    mbc.write_rom(0x6000, 0x01);
+
+    mbc.write_rom(0x4000, 0x08);
+    assert_eq!(mbc.read_ram(0xA000), 1);
+    mbc.write_rom(0x4000, 0x09);
+    assert_eq!(mbc.read_ram(0xBFFF), 1);
+
+    // Writing a clock register doesn't touch RAM.
+    mbc.write_ram(0xA000, 0x05);
+    mbc.write_rom(0x4000, 0x00);
+    assert_eq!(mbc.read_ram(0xA000), 0x42);
+}
+
+#[test]
+fn mbc3_rtc_needs_enable() {
+    let clock = ManualClock::new(0);
+    let mut mbc = Mbc3::with_rtc(banked_rom(2), 0, false, Box::new(clock.clone()));
+    mbc.write_rom(0x4000, 0x08);
+    assert_eq!(mbc.read_ram(0xA000), 0xFF);
+
+    mbc.write_rom(0x0000, 0x0A);
+    assert_eq!(mbc.read_ram(0xA000), 0x00);
+}
+
+#[test]
+fn mbc3_without_rtc() {
+    let mut mbc = Mbc3::new(banked_rom(2), 0x2000, false);
+    mbc.write_rom(0x0000, 0x0A);
+    mbc.write_rom(0x4000, 0x08);
+    mbc.write_ram(0xA000, 0x42);
+    assert_eq!(mbc.read_ram(0xA000), 0xFF);
+}
+
+#[test]
+fn mbc3_save_includes_clock() {
+    let clock = ManualClock::new(0);
+    let mut mbc = Mbc3::with_rtc(banked_rom(2), 0x2000, true, Box::new(clock.clone()));
+    mbc.write_rom(0x0000, 0x0A);
+    mbc.write_ram(0xA000, 0x42);
+    clock.advance(30);
+
+    let save = mbc.save_data().unwrap();
+    assert_eq!(save.len(), 0x2000 + FOOTER_SIZE);
+
+    clock.advance(10);
+    let mut restored = Mbc3::with_rtc(banked_rom(2), 0x2000, true, Box::new(clock.clone()));
+    restored.load_save_data(&save);
+    restored.write_rom(0x0000, 0x0A);
+    assert_eq!(restored.read_ram(0xA000), 0x42);
+
+    restored.write_rom(0x6000, 0x00);
+    restored.write_rom(0x6000, 0x01);
+    restored.write_rom(0x4000, 0x08);
+    assert_eq!(restored.read_ram(0xA000), 40);
+}
+
+#[test]
+fn mbc3_save_without_clock_footer() {
+    let clock = ManualClock::new(0);
+    let mut mbc = Mbc3::with_rtc(banked_rom(2), 0x2000, true, Box::new(clock.clone()));
+    mbc.load_save_data(&[0x42; 0x2000]);
+    mbc.write_rom(0x0000, 0x0A);
+    assert_eq!(mbc.read_ram(0xA000), 0x42);
+
+    assert_eq!(Mbc3::new(banked_rom(2), 0x2000, false).save_data(), None);
+}
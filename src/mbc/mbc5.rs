@@ -0,0 +1,207 @@
+use super::{bank_mask, battery_ram, load_ram, read_bank, Mbc, RumbleHandler, RAM_BANK_SIZE,
+            ROM_BANK_SIZE};
+#[cfg(test)]
+use super::banked_rom;
+#[cfg(test)]
+use std::cell::RefCell;
+#[cfg(test)]
+use std::rc::Rc;
+
+/// The rumble motor is wired to RAM bank bit 3.
+const RUMBLE: u8 = 0x08;
+
+/// MBC5, which supports up to 8 MiB of ROM and 128 KiB of RAM.
+///
+/// The ROM bank is nine bits, split across 2000-2FFF (low eight
+/// bits) and 3000-3FFF (bit 8). Unlike earlier MBCs, bank 0 can be
+/// mapped at 4000-7FFF.
+pub struct Mbc5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    rom_bank: u16,
+    ram_bank: u8,
+    battery: bool,
+    /// Rumble cartridges use bit 3 of the RAM bank for the motor.
+    has_rumble: bool,
+    rumbling: bool,
+    rumble_handler: Option<RumbleHandler>,
+}
+
+impl Mbc5 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, battery: bool, has_rumble: bool) -> Mbc5 {
+        Mbc5 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            battery,
+            has_rumble,
+            rumbling: false,
+            rumble_handler: None,
+        }
+    }
+
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return None;
+        }
+        let bank = self.ram_bank as usize & bank_mask(self.ram.len(), RAM_BANK_SIZE);
+        let offset = (address as usize - 0xA000) % self.ram.len().min(RAM_BANK_SIZE);
+        Some(bank * RAM_BANK_SIZE + offset)
+    }
+
+    fn set_rumble(&mut self, rumbling: bool) {
+        if rumbling != self.rumbling {
+            self.rumbling = rumbling;
+            if let Some(ref mut handler) = self.rumble_handler {
+                handler(rumbling);
+            }
+        }
+    }
+}
+
+impl Mbc for Mbc5 {
+    fn rom_size(&self) -> usize {
+        self.rom.len()
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        let bank = if address < 0x4000 {
+            0
+        } else {
+            self.rom_bank as usize & bank_mask(self.rom.len(), ROM_BANK_SIZE)
+        };
+        read_bank(&self.rom, bank, ROM_BANK_SIZE, address as usize)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            // MBC5 compares all eight bits, not just the low nibble.
+            0x0000..=0x1FFF => self.ram_enabled = value == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | ((value & 0x01) as u16) << 8,
+            0x4000..=0x5FFF => {
+                if self.has_rumble {
+                    self.ram_bank = value & 0x07;
+                    self.set_rumble(value & RUMBLE != 0);
+                } else {
+                    self.ram_bank = value & 0x0F;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        match self.ram_offset(address) {
+            Some(offset) => self.ram[offset],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if let Some(offset) = self.ram_offset(address) {
+            self.ram[offset] = value;
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        battery_ram(self.battery, &self.ram)
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn set_rumble_handler(&mut self, handler: RumbleHandler) {
+        self.rumble_handler = Some(handler);
+    }
+}
+
+#[test]
+fn mbc5_rom_banks() {
+    let mut mbc = Mbc5::new(banked_rom(512), 0, false, false);
+    assert_eq!(mbc.read_rom(0x4000), 1);
+
+    mbc.write_rom(0x2000, 0x42);
+    assert_eq!(mbc.read_rom(0x4000), 0x42);
+
+    // Bank 0 can be mapped at 4000-7FFF.
+    mbc.write_rom(0x2000, 0x00);
+    assert_eq!(mbc.read_rom(0x4000), 0);
+}
+
+#[test]
+fn mbc5_ninth_bank_bit() {
+    let mut rom = banked_rom(512);
+    rom[0x1FF * ROM_BANK_SIZE + 1] = 0xAB;
+    let mut mbc = Mbc5::new(rom, 0, false, false);
+
+    mbc.write_rom(0x3000, 0x01);
+    mbc.write_rom(0x2FFF, 0xFF);
+    assert_eq!(mbc.read_rom(0x4001), 0xAB);
+
+    // Only bit 0 of the upper register is used.
+    mbc.write_rom(0x3FFF, 0xFE);
+    assert_eq!(mbc.read_rom(0x4000), 0xFF);
+    assert_eq!(mbc.read_rom(0x0000), 0x00);
+}
+
+#[test]
+fn mbc5_ram_banks() {
+    let mut mbc = Mbc5::new(banked_rom(2), 0x20000, false, false);
+    mbc.write_rom(0x0000, 0x0A);
+    for bank in 0..16 {
+        mbc.write_rom(0x4000, bank);
+        mbc.write_ram(0xA000, bank + 0x10);
+    }
+
+    mbc.write_rom(0x4000, 0x0F);
+    assert_eq!(mbc.read_ram(0xA000), 0x1F);
+    mbc.write_rom(0x4000, 0x03);
+    assert_eq!(mbc.read_ram(0xA000), 0x13);
+}
+
+#[test]
+fn mbc5_ram_enable() {
+    let mut mbc = Mbc5::new(banked_rom(2), 0x2000, false, false);
+    mbc.write_rom(0x0000, 0x1A);
+    mbc.write_ram(0xA000, 0x42);
+    assert_eq!(mbc.read_ram(0xA000), 0xFF);
+
+    mbc.write_rom(0x0000, 0x0A);
+    mbc.write_ram(0xA000, 0x42);
+    assert_eq!(mbc.read_ram(0xA000), 0x42);
+}
+
+#[test]
+fn mbc5_rumble() {
+    let events = Rc::new(RefCell::new(vec![]));
+    let mut mbc = Mbc5::new(banked_rom(2), 0x8000, false, true);
+    let handler_events = events.clone();
+    mbc.set_rumble_handler(Box::new(move |on| handler_events.borrow_mut().push(on)));
+
+    mbc.write_rom(0x0000, 0x0A);
+    mbc.write_rom(0x4000, 0x09);
+    mbc.write_ram(0xA000, 0x42);
+    // Only changes to the motor are reported.
+    mbc.write_rom(0x4000, 0x0B);
+    mbc.write_rom(0x4000, 0x01);
+    assert_eq!(*events.borrow(), vec![true, false]);
+
+    // Bit 3 doesn't select a RAM bank on rumble carts.
+    assert_eq!(mbc.read_ram(0xA000), 0x42);
+}
+
+#[test]
+fn mbc5_without_rumble() {
+    let events = Rc::new(RefCell::new(vec![]));
+    let mut mbc = Mbc5::new(banked_rom(2), 0x20000, false, false);
+    let handler_events = events.clone();
+    mbc.set_rumble_handler(Box::new(move |on| handler_events.borrow_mut().push(on)));
+
+    mbc.write_rom(0x4000, 0x08);
+    assert!(events.borrow().is_empty());
+}
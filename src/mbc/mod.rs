@@ -0,0 +1,246 @@
+use std::cmp::min;
+
+use cartridge::{parse_header, Mapper, HEADER_END};
+
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod rtc;
+
+pub use self::mbc1::Mbc1;
+pub use self::mbc2::Mbc2;
+pub use self::mbc3::Mbc3;
+pub use self::mbc5::Mbc5;
+pub use self::rtc::{ManualClock, SystemClock, TimeSource};
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+/// Called with true when a cartridge's rumble motor starts, and false
+/// when it stops.
+pub type RumbleHandler = Box<dyn FnMut(bool)>;
+
+/// A memory bank controller. This sits between the CPU and the
+/// cartridge, mapping banks of ROM into 0000-7FFF and banks of
+/// external RAM into A000-BFFF.
+pub trait Mbc {
+    /// The size of the ROM image, in bytes.
+    fn rom_size(&self) -> usize;
+    /// Read from 0000-7FFF.
+    fn read_rom(&self, address: u16) -> u8;
+    /// Write to 0000-7FFF. ROM can't be written to, so MBCs use these
+    /// writes to set their registers.
+    fn write_rom(&mut self, address: u16, value: u8);
+    /// Read from A000-BFFF.
+    fn read_ram(&self, address: u16) -> u8;
+    /// Write to A000-BFFF.
+    fn write_ram(&mut self, address: u16, value: u8);
+
+    /// The contents of battery-backed RAM, for writing to a save
+    /// file. None if the cartridge has no battery.
+    fn save_data(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Restore battery-backed RAM from a save file.
+    fn load_save_data(&mut self, _data: &[u8]) {}
+
+    /// Be told when the rumble motor turns on or off. Cartridges
+    /// without a motor never call the handler.
+    fn set_rumble_handler(&mut self, _handler: RumbleHandler) {}
+}
+
+/// A cartridge with no MBC: 32 KiB of ROM and optionally up to 8 KiB
+/// of RAM.
+pub struct RomOnly {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    battery: bool,
+}
+
+impl RomOnly {
+    pub fn new(rom: Vec<u8>, ram_size: usize, battery: bool) -> RomOnly {
+        RomOnly {
+            rom,
+            ram: vec![0; ram_size],
+            battery,
+        }
+    }
+}
+
+impl Mbc for RomOnly {
+    fn rom_size(&self) -> usize {
+        self.rom.len()
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        read_bank(&self.rom, 0, ROM_BANK_SIZE, address as usize)
+    }
+
+    fn write_rom(&mut self, _: u16, _: u8) {}
+
+    fn read_ram(&self, address: u16) -> u8 {
+        let offset = address as usize - 0xA000;
+        *self.ram.get(offset).unwrap_or(&0xFF)
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        let offset = address as usize - 0xA000;
+        if offset < self.ram.len() {
+            self.ram[offset] = value;
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        battery_ram(self.battery, &self.ram)
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+}
+
+/// Choose an MBC based on the cartridge type in the ROM header. ROMs
+/// too short to have a header are treated as plain ROMs, but a header
+/// we can't parse is an error.
+pub fn from_rom(rom: Vec<u8>) -> Result<Box<dyn Mbc>, String> {
+    if rom.len() < HEADER_END {
+        return Ok(Box::new(RomOnly::new(rom, 0, false)));
+    }
+    let header = parse_header(&rom)?;
+    let cartridge_type = header.cartridge_type
+        .ok_or_else(|| format!("Unknown cartridge type ${:02X}", header.cartridge_type_code))?;
+    if header.rom_size.is_none() {
+        return Err(format!("Unknown ROM size ${:02X}", header.rom_size_code));
+    }
+    let ram_size = header.ram_size
+        .ok_or_else(|| format!("Unknown RAM size ${:02X}", header.ram_size_code))?;
+
+    let battery = cartridge_type.battery;
+    match cartridge_type.mapper {
+        Mapper::RomOnly => Ok(Box::new(RomOnly::new(rom, ram_size, battery))),
+        Mapper::Mbc1 => Ok(Box::new(Mbc1::new(rom, ram_size, battery))),
+        Mapper::Mbc2 => Ok(Box::new(Mbc2::new(rom, battery))),
+        Mapper::Mbc3 if cartridge_type.timer => {
+            Ok(Box::new(Mbc3::with_rtc(rom, ram_size, battery, Box::new(SystemClock))))
+        }
+        Mapper::Mbc3 => Ok(Box::new(Mbc3::new(rom, ram_size, battery))),
+        Mapper::Mbc5 => {
+            let rumble = cartridge_type.rumble;
+            Ok(Box::new(Mbc5::new(rom, ram_size, battery, rumble)))
+        }
+        _ => Err(format!("Unsupported cartridge type {}", cartridge_type)),
+    }
+}
+
+/// Read from a bank of ROM or RAM. Reading past the end of the data
+/// gives open bus.
+fn read_bank(data: &[u8], bank: usize, bank_size: usize, offset: usize) -> u8 {
+    *data.get(bank * bank_size + offset % bank_size).unwrap_or(&0xFF)
+}
+
+/// Save data is only kept for cartridges with a battery, and only if
+/// they have RAM to save.
+fn battery_ram(battery: bool, ram: &[u8]) -> Option<Vec<u8>> {
+    if battery && !ram.is_empty() {
+        Some(ram.to_vec())
+    } else {
+        None
+    }
+}
+
+/// Copy save data into RAM. Save files from other emulators may be
+/// padded or truncated, so copy as much as fits.
+fn load_ram(ram: &mut [u8], data: &[u8]) {
+    let len = min(ram.len(), data.len());
+    ram[..len].copy_from_slice(&data[..len]);
+}
+
+/// The mask to apply to a bank number, since MBCs ignore bank bits
+/// beyond the size of the chip.
+fn bank_mask(size: usize, bank_size: usize) -> usize {
+    let banks = size.div_ceil(bank_size);
+    banks.next_power_of_two().max(1) - 1
+}
+
+// A ROM where the first byte of each bank is the bank number.
+#[cfg(test)]
+fn banked_rom(banks: usize) -> Vec<u8> {
+    let mut rom = vec![0; banks * ROM_BANK_SIZE];
+    for bank in 0..banks {
+        rom[bank * ROM_BANK_SIZE] = bank as u8;
+    }
+    rom
+}
+
+#[test]
+fn rom_only() {
+    let mut mbc = RomOnly::new(vec![0x12; 0x8000], 0x2000, false);
+    mbc.write_rom(0x2000, 0x05);
+    assert_eq!(mbc.read_rom(0x4000), 0x12);
+
+    mbc.write_ram(0xA010, 0x34);
+    assert_eq!(mbc.read_ram(0xA010), 0x34);
+}
+
+#[test]
+fn rom_only_without_ram() {
+    let mut mbc = RomOnly::new(vec![0x12; 0x100], 0, false);
+    mbc.write_ram(0xA000, 0x34);
+    assert_eq!(mbc.read_ram(0xA000), 0xFF);
+    assert_eq!(mbc.read_rom(0x0100), 0xFF);
+}
+
+#[test]
+fn headerless_rom() {
+    let mbc = from_rom(vec![0x00, 0x3C]).unwrap();
+    assert_eq!(mbc.read_rom(0x0001), 0x3C);
+}
+
+#[test]
+fn unknown_cartridge_type() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x147] = 0x23;
+    assert!(from_rom(rom).is_err());
+}
+
+#[test]
+fn unknown_sizes() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x148] = 0x52;
+    assert!(from_rom(rom).is_err());
+
+    let mut rom = vec![0; 0x8000];
+    rom[0x149] = 0x07;
+    assert!(from_rom(rom).is_err());
+}
+
+#[test]
+fn unsupported_cartridge_type() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x147] = 0x0B; // MMM01
+    assert!(from_rom(rom).is_err());
+}
+
+#[test]
+fn save_data_needs_battery() {
+    let mut mbc = RomOnly::new(vec![], 0x2000, false);
+    mbc.write_ram(0xA000, 0x42);
+    assert_eq!(mbc.save_data(), None);
+
+    let mut mbc = RomOnly::new(vec![], 0x2000, true);
+    mbc.load_save_data(&[0x42, 0x43]);
+    assert_eq!(mbc.read_ram(0xA001), 0x43);
+    assert_eq!(mbc.save_data().unwrap().len(), 0x2000);
+
+    assert_eq!(RomOnly::new(vec![], 0, true).save_data(), None);
+}
+
+#[test]
+fn bank_masks() {
+    assert_eq!(bank_mask(0x8000, ROM_BANK_SIZE), 0x01);
+    assert_eq!(bank_mask(0x100000, ROM_BANK_SIZE), 0x3F);
+    assert_eq!(bank_mask(0x800, RAM_BANK_SIZE), 0x00);
+    assert_eq!(bank_mask(0, RAM_BANK_SIZE), 0x00);
+}
@@ -0,0 +1,356 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where the cartridge clock gets the time from.
+pub trait TimeSource {
+    /// Seconds since the Unix epoch.
+    fn now(&self) -> u64;
+}
+
+/// The host's clock.
+pub struct SystemClock;
+
+impl TimeSource for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+/// A clock that only moves when told to, for tests and for frontends
+/// that want emulated time. Clones share the same time.
+#[derive(Clone, Default)]
+pub struct ManualClock(Rc<Cell<u64>>);
+
+impl ManualClock {
+    pub fn new(now: u64) -> ManualClock {
+        ManualClock(Rc::new(Cell::new(now)))
+    }
+
+    pub fn advance(&self, seconds: u64) {
+        self.0.set(self.0.get() + seconds);
+    }
+}
+
+impl TimeSource for ManualClock {
+    fn now(&self) -> u64 {
+        self.0.get()
+    }
+}
+
+const SECONDS: u8 = 0x08;
+const MINUTES: u8 = 0x09;
+const HOURS: u8 = 0x0A;
+const DAY_LOW: u8 = 0x0B;
+const DAY_HIGH: u8 = 0x0C;
+
+const DAY_HIGH_BIT: u8 = 0x01;
+const HALT: u8 = 0x40;
+const DAY_CARRY: u8 = 0x80;
+
+/// The size of the clock state appended to save RAM.
+pub const FOOTER_SIZE: usize = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct Registers {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    /// Nine bits.
+    days: u16,
+    halted: bool,
+    /// Set when the day counter overflows, until it's cleared by
+    /// the game.
+    day_carry: bool,
+}
+
+impl Registers {
+    fn read(&self, register: u8) -> u8 {
+        match register {
+            SECONDS => self.seconds,
+            MINUTES => self.minutes,
+            HOURS => self.hours,
+            DAY_LOW => self.days as u8,
+            _ => {
+                let mut value = (self.days >> 8) as u8 & DAY_HIGH_BIT;
+                if self.halted {
+                    value |= HALT;
+                }
+                if self.day_carry {
+                    value |= DAY_CARRY;
+                }
+                value
+            }
+        }
+    }
+
+    fn write(&mut self, register: u8, value: u8) {
+        match register {
+            SECONDS => self.seconds = value & 0x3F,
+            MINUTES => self.minutes = value & 0x3F,
+            HOURS => self.hours = value & 0x1F,
+            DAY_LOW => self.days = (self.days & 0x100) | value as u16,
+            _ => {
+                self.days = (self.days & 0xFF) | ((value & DAY_HIGH_BIT) as u16) << 8;
+                self.halted = value & HALT != 0;
+                self.day_carry = value & DAY_CARRY != 0;
+            }
+        }
+    }
+
+    /// The registers after the clock has run for `elapsed` seconds.
+    fn advance(mut self, elapsed: u64) -> Registers {
+        if self.halted || elapsed == 0 {
+            return self;
+        }
+
+        let seconds = self.seconds as u64 + elapsed;
+        self.seconds = (seconds % 60) as u8;
+        let minutes = self.minutes as u64 + seconds / 60;
+        self.minutes = (minutes % 60) as u8;
+        let hours = self.hours as u64 + minutes / 60;
+        self.hours = (hours % 24) as u8;
+        let days = self.days as u64 + hours / 24;
+        self.days = (days % 512) as u16;
+        if days >= 512 {
+            self.day_carry = true;
+        }
+        self
+    }
+
+    fn to_footer(self, bytes: &mut Vec<u8>) {
+        for register in SECONDS..=DAY_HIGH {
+            bytes.extend_from_slice(&(self.read(register) as u32).to_le_bytes());
+        }
+    }
+
+    fn from_footer(bytes: &[u8]) -> Registers {
+        let mut registers = Registers::default();
+        for (i, register) in (SECONDS..=DAY_HIGH).enumerate() {
+            registers.write(register, bytes[i * 4]);
+        }
+        registers
+    }
+}
+
+/// The MBC3 real time clock. The game sees a latched copy of the
+/// registers, which is only updated when it writes 0 then 1 to
+/// 6000-7FFF.
+pub struct Rtc {
+    clock: Box<dyn TimeSource>,
+    current: Registers,
+    latched: Registers,
+    /// When `current` was last brought up to date.
+    last_update: u64,
+    last_latch_write: u8,
+}
+
+impl Rtc {
+    pub fn new(clock: Box<dyn TimeSource>) -> Rtc {
+        let now = clock.now();
+        Rtc {
+            clock,
+            current: Registers::default(),
+            latched: Registers::default(),
+            last_update: now,
+            last_latch_write: 0xFF,
+        }
+    }
+
+    /// Read one of the registers 08-0C.
+    pub fn read(&self, register: u8) -> u8 {
+        self.latched.read(register)
+    }
+
+    /// Write one of the registers 08-0C. This sets the running clock
+    /// as well as the latched copy.
+    pub fn write(&mut self, register: u8, value: u8) {
+        self.update();
+        self.current.write(register, value);
+        self.latched.write(register, value);
+    }
+
+    /// Handle a write to 6000-7FFF.
+    pub fn write_latch(&mut self, value: u8) {
+        if self.last_latch_write == 0x00 && value == 0x01 {
+            self.update();
+            self.latched = self.current;
+        }
+        self.last_latch_write = value;
+    }
+
+    fn update(&mut self) {
+        let now = self.clock.now();
+        self.current = self.current.advance(now.saturating_sub(self.last_update));
+        self.last_update = now;
+    }
+
+    /// The clock state in the format used by most emulators: the
+    /// current and latched registers as 32-bit values, followed by a
+    /// 64-bit Unix timestamp, all little endian.
+    pub fn footer(&self) -> Vec<u8> {
+        let now = self.clock.now();
+        let current = self.current.advance(now.saturating_sub(self.last_update));
+
+        let mut bytes = Vec::with_capacity(FOOTER_SIZE);
+        current.to_footer(&mut bytes);
+        self.latched.to_footer(&mut bytes);
+        bytes.extend_from_slice(&now.to_le_bytes());
+        bytes
+    }
+
+    /// Restore the clock from a save file footer. The clock keeps
+    /// running while the emulator isn't, so time since the save is
+    /// added on.
+    pub fn load_footer(&mut self, bytes: &[u8]) {
+        if bytes.len() < FOOTER_SIZE {
+            return;
+        }
+        self.current = Registers::from_footer(&bytes[..20]);
+        self.latched = Registers::from_footer(&bytes[20..40]);
+
+        let mut timestamp = [0; 8];
+        timestamp.copy_from_slice(&bytes[40..48]);
+        self.last_update = u64::from_le_bytes(timestamp);
+        self.update();
+    }
+}
+
+#[cfg(test)]
+fn latch(rtc: &mut Rtc) {
+    rtc.write_latch(0x00);
+    rtc.write_latch(0x01);
+}
+
+#[test]
+fn rtc_reads_latched_registers() {
+    let clock = ManualClock::new(1000);
+    let mut rtc = Rtc::new(Box::new(clock.clone()));
+
+    clock.advance(5);
+    assert_eq!(rtc.read(SECONDS), 0);
+
+    latch(&mut rtc);
+    assert_eq!(rtc.read(SECONDS), 5);
+
+    clock.advance(5);
+    assert_eq!(rtc.read(SECONDS), 5);
+}
+
+#[test]
+fn rtc_latch_needs_zero_then_one() {
+    let clock = ManualClock::new(0);
+    let mut rtc = Rtc::new(Box::new(clock.clone()));
+    clock.advance(3);
+
+    rtc.write_latch(0x01);
+    assert_eq!(rtc.read(SECONDS), 0);
+    rtc.write_latch(0x01);
+    assert_eq!(rtc.read(SECONDS), 0);
+
+    rtc.write_latch(0x00);
+    rtc.write_latch(0x01);
+    assert_eq!(rtc.read(SECONDS), 3);
+}
+
+#[test]
+fn rtc_rolls_over() {
+    let clock = ManualClock::new(0);
+    let mut rtc = Rtc::new(Box::new(clock.clone()));
+
+    // 1 day, 2 hours, 3 minutes and 4 seconds.
+    clock.advance(86400 + 2 * 3600 + 3 * 60 + 4);
+    latch(&mut rtc);
+    assert_eq!(rtc.read(SECONDS), 4);
+    assert_eq!(rtc.read(MINUTES), 3);
+    assert_eq!(rtc.read(HOURS), 2);
+    assert_eq!(rtc.read(DAY_LOW), 1);
+    assert_eq!(rtc.read(DAY_HIGH), 0);
+
+    clock.advance(256 * 86400);
+    latch(&mut rtc);
+    assert_eq!(rtc.read(DAY_LOW), 1);
+    assert_eq!(rtc.read(DAY_HIGH), DAY_HIGH_BIT);
+}
+
+#[test]
+fn rtc_day_carry() {
+    let clock = ManualClock::new(0);
+    let mut rtc = Rtc::new(Box::new(clock.clone()));
+    rtc.write(DAY_LOW, 0xFF);
+    rtc.write(DAY_HIGH, DAY_HIGH_BIT);
+
+    clock.advance(86400);
+    latch(&mut rtc);
+    assert_eq!(rtc.read(DAY_LOW), 0);
+    assert_eq!(rtc.read(DAY_HIGH), DAY_CARRY);
+
+    // The carry stays set until it's cleared.
+    clock.advance(86400);
+    latch(&mut rtc);
+    assert_eq!(rtc.read(DAY_HIGH), DAY_CARRY);
+
+    rtc.write(DAY_HIGH, 0x00);
+    assert_eq!(rtc.read(DAY_HIGH), 0x00);
+}
+
+#[test]
+fn rtc_halt() {
+    let clock = ManualClock::new(0);
+    let mut rtc = Rtc::new(Box::new(clock.clone()));
+    clock.advance(10);
+    rtc.write(DAY_HIGH, HALT);
+
+    clock.advance(100);
+    latch(&mut rtc);
+    assert_eq!(rtc.read(SECONDS), 10);
+    assert_eq!(rtc.read(DAY_HIGH), HALT);
+
+    rtc.write(DAY_HIGH, 0x00);
+    clock.advance(5);
+    latch(&mut rtc);
+    assert_eq!(rtc.read(SECONDS), 15);
+}
+
+#[test]
+fn rtc_write() {
+    let clock = ManualClock::new(0);
+    let mut rtc = Rtc::new(Box::new(clock.clone()));
+    rtc.write(MINUTES, 59);
+    rtc.write(SECONDS, 59);
+    assert_eq!(rtc.read(MINUTES), 59);
+
+    clock.advance(1);
+    latch(&mut rtc);
+    assert_eq!(rtc.read(SECONDS), 0);
+    assert_eq!(rtc.read(MINUTES), 0);
+    assert_eq!(rtc.read(HOURS), 1);
+}
+
+#[test]
+fn rtc_footer_round_trip() {
+    let clock = ManualClock::new(1_500_000_000);
+    let mut rtc = Rtc::new(Box::new(clock.clone()));
+    clock.advance(42);
+    latch(&mut rtc);
+    clock.advance(8);
+
+    let footer = rtc.footer();
+    assert_eq!(footer.len(), FOOTER_SIZE);
+    assert_eq!(&footer[0..4], &[50, 0, 0, 0]);
+    assert_eq!(&footer[20..24], &[42, 0, 0, 0]);
+    assert_eq!(&footer[40..48], &1_500_000_050u64.to_le_bytes());
+
+    // Ten seconds pass while the emulator is closed.
+    let later = ManualClock::new(1_500_000_060);
+    let mut restored = Rtc::new(Box::new(later.clone()));
+    restored.load_footer(&footer);
+    assert_eq!(restored.read(SECONDS), 42);
+
+    latch(&mut restored);
+    assert_eq!(restored.read(SECONDS), 0);
+    assert_eq!(restored.read(MINUTES), 1);
+}
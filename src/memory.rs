@@ -0,0 +1,483 @@
+use interrupts::{Interrupt, IF_ADDRESS};
+use joypad::{Buttons, Joypad, P1_ADDRESS};
+use link::Link;
+use mbc::{self, Mbc, RumbleHandler};
+use model::Model;
+use ppu::{self, Ppu};
+use serial::{Serial, SerialOutput, SB_ADDRESS, SC_ADDRESS};
+use timer::{Timer, DIV_ADDRESS, TAC_ADDRESS};
+
+/// Writing a non-zero value here unmaps the boot ROM.
+pub const BOOT_ROM_DISABLE_ADDRESS: u16 = 0xFF50;
+
+/// I/O registers as the DMG boot ROM leaves them. Other models differ
+/// in a few registers, see `post_boot_io_overrides`.
+const POST_BOOT_IO_REGISTERS: [(u16, u8); 36] = [
+    (0xFF00, 0xCF), // P1
+    (0xFF01, 0x00), // SB
+    (0xFF02, 0x7E), // SC
+    (0xFF05, 0x00), // TIMA
+    (0xFF06, 0x00), // TMA
+    (0xFF07, 0xF8), // TAC
+    (0xFF0F, 0xE1), // IF
+    (0xFF10, 0x80), // NR10
+    (0xFF11, 0xBF), // NR11
+    (0xFF12, 0xF3), // NR12
+    (0xFF13, 0xFF), // NR13
+    (0xFF14, 0xBF), // NR14
+    (0xFF16, 0x3F), // NR21
+    (0xFF17, 0x00), // NR22
+    (0xFF18, 0xFF), // NR23
+    (0xFF19, 0xBF), // NR24
+    (0xFF1A, 0x7F), // NR30
+    (0xFF1B, 0xFF), // NR31
+    (0xFF1C, 0x9F), // NR32
+    (0xFF1D, 0xFF), // NR33
+    (0xFF1E, 0xBF), // NR34
+    (0xFF20, 0xFF), // NR41
+    (0xFF21, 0x00), // NR42
+    (0xFF22, 0x00), // NR43
+    (0xFF23, 0xBF), // NR44
+    (0xFF24, 0x77), // NR50
+    (0xFF25, 0xF3), // NR51
+    (0xFF26, 0xF1), // NR52
+    (0xFF40, 0x91), // LCDC
+    (0xFF41, 0x85), // STAT
+    (0xFF42, 0x00), // SCY
+    (0xFF43, 0x00), // SCX
+    (0xFF45, 0x00), // LYC
+    (0xFF46, 0xFF), // DMA
+    (0xFF47, 0xFC), // BGP
+    (0xFF4A, 0x00), // WY
+];
+
+/// Post-boot I/O registers that depend on the model.
+fn post_boot_io_overrides(model: Model) -> [(u16, u8); 2] {
+    match model {
+        Model::Dmg0 => [(0xFF41, 0x81), (0xFF46, 0xFF)],
+        Model::Dmg | Model::Mgb | Model::Sgb | Model::Sgb2 => [(0xFF41, 0x85), (0xFF46, 0xFF)],
+        Model::Cgb | Model::Agb => [(0xFF41, 0x85), (0xFF46, 0x00)],
+    }
+}
+
+/// The timer's internal counter after the boot ROM. DIV is the upper
+/// byte, and depends on how long the boot ROM took to run.
+///
+/// Only the DMG0, DMG and MGB values are documented. The SGB boot ROM's
+/// run time depends on the SNES, and the CGB boot ROM's depends on the
+/// cartridge header, so for those models 0 is a placeholder rather
+/// than what the hardware would have.
+fn post_boot_divider(model: Model) -> u16 {
+    match model {
+        Model::Dmg0 => 0x1830,
+        Model::Dmg | Model::Mgb => 0xABCC,
+        // Placeholders, see above.
+        Model::Sgb | Model::Sgb2 | Model::Cgb | Model::Agb => 0x0000,
+    }
+}
+
+/// I/O addresses with no register behind them on DMG. They read as
+/// 0xFF and ignore writes. This includes the CGB-only registers, since
+/// we don't emulate CGB mode.
+fn is_unmapped_io(address: u16) -> bool {
+    match address {
+        0xFF03 | 0xFF08..=0xFF0E | 0xFF15 | 0xFF1F | 0xFF27..=0xFF2F => true,
+        0xFF4C..=0xFF7F => address != BOOT_ROM_DISABLE_ADDRESS,
+        _ => false,
+    }
+}
+
+/// The Game Boy's 16-bit address space. Reads and writes are routed
+/// to the component that owns each region:
+///
+/// 0000-7FFF  Cartridge ROM, banked by the MBC. The boot ROM covers
+///            0000-00FF (and 0200-08FF on CGB) until it's unmapped.
+/// 8000-9FFF  Video RAM
+/// A000-BFFF  External (cartridge) RAM, banked by the MBC
+/// C000-DFFF  Work RAM
+/// E000-FDFF  Echo RAM, a mirror of C000-DDFF
+/// FE00-FE9F  Object attribute memory (OAM)
+/// FEA0-FEFF  Unusable
+/// FF00-FF7F  I/O registers, including the joypad at FF00, the serial
+///            port at FF01-FF02, the timer at FF04-FF07 and the LCD
+///            at FF40-FF4B. Unmapped registers read as FF.
+/// FF80-FFFE  High RAM
+/// FFFF       Interrupt enable register
+pub struct Memory {
+    cartridge: Box<dyn Mbc>,
+    boot_rom: Option<Vec<u8>>,
+    wram: [u8; 0x2000],
+    io: [u8; 0x80],
+    hram: [u8; 0x7F],
+    ie: u8,
+    timer: Timer,
+    joypad: Joypad,
+    serial: Serial,
+    ppu: Ppu,
+}
+
+impl Memory {
+    pub fn new(cartridge: Box<dyn Mbc>) -> Memory {
+        Memory {
+            cartridge,
+            boot_rom: None,
+            wram: [0; 0x2000],
+            io: [0; 0x80],
+            hram: [0; 0x7F],
+            ie: 0,
+            timer: Timer::new(),
+            joypad: Joypad::new(),
+            serial: Serial::new(),
+            ppu: Ppu::new(),
+        }
+    }
+
+    /// Build the memory map for a ROM image, choosing the MBC from
+    /// its header.
+    pub fn from_rom(rom: Vec<u8>) -> Result<Memory, String> {
+        Ok(Memory::new(mbc::from_rom(rom)?))
+    }
+
+    /// Map a boot ROM over the start of cartridge ROM. The boot ROM
+    /// unmaps itself by writing to FF50 when it's done.
+    pub fn map_boot_rom(&mut self, boot_rom: Vec<u8>, model: Model) -> Result<(), String> {
+        if boot_rom.len() != model.boot_rom_size() {
+            return Err(format!("{:?} boot ROM should be {} bytes, but it's {} bytes",
+                               model, model.boot_rom_size(), boot_rom.len()));
+        }
+        self.boot_rom = Some(boot_rom);
+        Ok(())
+    }
+
+    /// Set the I/O registers to the values the boot ROM leaves them
+    /// with, for running without a boot ROM.
+    pub fn skip_boot_rom(&mut self, model: Model) {
+        self.boot_rom = None;
+        let overrides = post_boot_io_overrides(model);
+        for &(address, value) in POST_BOOT_IO_REGISTERS.iter().chain(overrides.iter()) {
+            self.write(address, value);
+        }
+        self.timer.set_counter(post_boot_divider(model));
+    }
+
+    /// Advance the rest of the hardware by the given number of
+    /// M-cycles, requesting any interrupts it raises.
+    pub fn tick(&mut self, cycles: u32) {
+        for _ in 0..cycles {
+            if self.timer.tick() {
+                self.request_interrupt(Interrupt::Timer);
+            }
+            if self.serial.tick() {
+                self.request_interrupt(Interrupt::Serial);
+            }
+            let interrupts = self.ppu.tick();
+            if interrupts.vblank {
+                self.request_interrupt(Interrupt::VBlank);
+            }
+            if interrupts.stat {
+                self.request_interrupt(Interrupt::LcdStat);
+            }
+        }
+    }
+
+    /// The size of the cartridge ROM, in bytes.
+    pub fn rom_size(&self) -> usize {
+        self.cartridge.rom_size()
+    }
+
+    /// Choose where bytes sent over the serial port go.
+    pub fn set_serial_output(&mut self, output: SerialOutput) {
+        self.serial.set_output(output);
+    }
+
+    /// Connect a link cable to the serial port.
+    pub fn set_link(&mut self, link: Box<dyn Link>) {
+        self.serial.set_link(link);
+    }
+
+    /// Bytes sent over the serial port, when they're being kept in a
+    /// buffer.
+    pub fn serial_output(&self) -> &[u8] {
+        self.serial.output()
+    }
+
+    /// The last complete frame drawn by the PPU.
+    pub fn frame(&self) -> &[u8] {
+        self.ppu.frame()
+    }
+
+    /// How many frames the PPU has completed.
+    pub fn frame_count(&self) -> u64 {
+        self.ppu.frame_count()
+    }
+
+    /// Press and release buttons on the joypad.
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        if self.joypad.set_buttons(buttons) {
+            self.request_interrupt(Interrupt::Joypad);
+        }
+    }
+
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.io[IF_ADDRESS as usize - 0xFF00] |= interrupt.bit();
+    }
+
+    /// Battery-backed cartridge RAM, see `Mbc::save_data`.
+    pub fn save_data(&self) -> Option<Vec<u8>> {
+        self.cartridge.save_data()
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        self.cartridge.load_save_data(data);
+    }
+
+    /// Be told when the cartridge's rumble motor turns on or off.
+    pub fn set_rumble_handler(&mut self, handler: RumbleHandler) {
+        self.cartridge.set_rumble_handler(handler);
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        let address = address as usize;
+        match address {
+            0x0000..=0x7FFF => match self.boot_rom {
+                // The CGB boot ROM skips 0100-01FF, so the cartridge
+                // header is visible.
+                Some(ref boot_rom) if address < 0x100 || (0x200..boot_rom.len()).contains(&address) => {
+                    boot_rom[address]
+                }
+                _ => self.cartridge.read_rom(address as u16),
+            },
+            0x8000..=0x9FFF => self.ppu.read_vram(address as u16),
+            0xA000..=0xBFFF => self.cartridge.read_ram(address as u16),
+            0xC000..=0xDFFF => self.wram[address - 0xC000],
+            0xE000..=0xFDFF => self.wram[address - 0xE000],
+            0xFE00..=0xFE9F => self.ppu.read_oam(address as u16),
+            0xFEA0..=0xFEFF => 0x00,
+            // The top three bits of IF are unused and always read as 1.
+            _ if address == IF_ADDRESS as usize => self.io[address - 0xFF00] | 0xE0,
+            _ if address == BOOT_ROM_DISABLE_ADDRESS as usize => 0xFF,
+            _ if address == P1_ADDRESS as usize => self.joypad.read(),
+            _ if address == SB_ADDRESS as usize || address == SC_ADDRESS as usize => {
+                self.serial.read(address as u16)
+            }
+            _ if (DIV_ADDRESS as usize..=TAC_ADDRESS as usize).contains(&address) => {
+                self.timer.read(address as u16)
+            }
+            _ if ppu::is_register(address as u16) => self.ppu.read(address as u16),
+            _ if is_unmapped_io(address as u16) => 0xFF,
+            0xFF00..=0xFF7F => self.io[address - 0xFF00],
+            0xFF80..=0xFFFE => self.hram[address - 0xFF80],
+            _ => self.ie,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        let address = address as usize;
+        match address {
+            0x0000..=0x7FFF => self.cartridge.write_rom(address as u16, value),
+            0x8000..=0x9FFF => self.ppu.write_vram(address as u16, value),
+            0xA000..=0xBFFF => self.cartridge.write_ram(address as u16, value),
+            0xC000..=0xDFFF => self.wram[address - 0xC000] = value,
+            0xE000..=0xFDFF => self.wram[address - 0xE000] = value,
+            0xFE00..=0xFE9F => self.ppu.write_oam(address as u16, value),
+            0xFEA0..=0xFEFF => {}
+            // Once the boot ROM is unmapped, it can't be mapped again.
+            _ if address == BOOT_ROM_DISABLE_ADDRESS as usize => {
+                if value != 0 {
+                    self.boot_rom = None;
+                }
+            }
+            _ if address == P1_ADDRESS as usize => {
+                if self.joypad.write(value) {
+                    self.request_interrupt(Interrupt::Joypad);
+                }
+            }
+            _ if address == SB_ADDRESS as usize || address == SC_ADDRESS as usize => {
+                self.serial.write(address as u16, value)
+            }
+            _ if (DIV_ADDRESS as usize..=TAC_ADDRESS as usize).contains(&address) => {
+                self.timer.write(address as u16, value)
+            }
+            _ if ppu::is_register(address as u16) => {
+                if self.ppu.write(address as u16, value) {
+                    self.request_interrupt(Interrupt::LcdStat);
+                }
+            }
+            _ if is_unmapped_io(address as u16) => {}
+            0xFF00..=0xFF7F => self.io[address - 0xFF00] = value,
+            0xFF80..=0xFFFE => self.hram[address - 0xFF80] = value,
+            _ => self.ie = value,
+        }
+    }
+}
+
+#[test]
+fn rom_is_read_only() {
+    let mut memory = Memory::from_rom(vec![0x12, 0x34]).unwrap();
+    memory.write(0x0001, 0xFF);
+
+    assert_eq!(memory.read(0x0000), 0x12);
+    assert_eq!(memory.read(0x0001), 0x34);
+    assert_eq!(memory.read(0x0002), 0xFF);
+}
+
+#[test]
+fn echo_ram() {
+    let mut memory = Memory::from_rom(vec![]).unwrap();
+    memory.write(0xC123, 0x42);
+    assert_eq!(memory.read(0xE123), 0x42);
+
+    memory.write(0xFDFF, 0x24);
+    assert_eq!(memory.read(0xDDFF), 0x24);
+}
+
+#[test]
+fn unusable_region() {
+    let mut memory = Memory::from_rom(vec![]).unwrap();
+    memory.write(0xFEA0, 0x42);
+    assert_eq!(memory.read(0xFEA0), 0x00);
+}
+
+#[test]
+fn unmapped_io_registers() {
+    let mut memory = Memory::from_rom(vec![]).unwrap();
+    for &address in &[0xFF03, 0xFF08, 0xFF0E, 0xFF27, 0xFF2F, 0xFF4C, 0xFF7F] {
+        assert_eq!(memory.read(address), 0xFF);
+        memory.write(address, 0x42);
+        assert_eq!(memory.read(address), 0xFF);
+    }
+
+    // Sound registers aren't emulated, but they do exist.
+    memory.write(0xFF24, 0x42);
+    assert_eq!(memory.read(0xFF24), 0x42);
+}
+
+#[test]
+fn no_external_ram() {
+    let mut memory = Memory::from_rom(vec![]).unwrap();
+    memory.write(0xA000, 0x42);
+    assert_eq!(memory.read(0xA000), 0xFF);
+}
+
+#[test]
+fn interrupt_registers() {
+    let mut memory = Memory::from_rom(vec![]).unwrap();
+    memory.write(0xFF0F, 0x01);
+    memory.write(0xFFFF, 0x1F);
+
+    assert_eq!(memory.read(0xFF0F), 0xE1);
+    assert_eq!(memory.read(0xFFFF), 0x1F);
+}
+
+#[test]
+fn vram_oam_hram() {
+    let mut memory = Memory::from_rom(vec![]).unwrap();
+    memory.write(0x8000, 0x01);
+    memory.write(0xFE9F, 0x02);
+    memory.write(0xFFFE, 0x03);
+
+    assert_eq!(memory.read(0x8000), 0x01);
+    assert_eq!(memory.read(0xFE9F), 0x02);
+    assert_eq!(memory.read(0xFFFE), 0x03);
+}
+
+#[test]
+fn boot_rom_overlay() {
+    let mut memory = Memory::new(Box::new(mbc::RomOnly::new(vec![0x11; 0x200], 0, false)));
+    memory.map_boot_rom(vec![0x22; 0x100], Model::Dmg).unwrap();
+    assert_eq!(memory.read(0x0000), 0x22);
+    assert_eq!(memory.read(0x00FF), 0x22);
+    assert_eq!(memory.read(0x0100), 0x11);
+
+    memory.write(BOOT_ROM_DISABLE_ADDRESS, 0x00);
+    assert_eq!(memory.read(0x0000), 0x22);
+
+    memory.write(BOOT_ROM_DISABLE_ADDRESS, 0x01);
+    assert_eq!(memory.read(0x0000), 0x11);
+    assert_eq!(memory.read(BOOT_ROM_DISABLE_ADDRESS), 0xFF);
+}
+
+#[test]
+fn boot_rom_size() {
+    let mut memory = Memory::from_rom(vec![]).unwrap();
+    assert!(memory.map_boot_rom(vec![0; 0x80], Model::Dmg).is_err());
+    assert!(memory.map_boot_rom(vec![0; 0x100], Model::Cgb).is_err());
+    assert!(memory.map_boot_rom(vec![0; 0x900], Model::Cgb).is_ok());
+}
+
+#[test]
+fn cgb_boot_rom_overlay() {
+    let mut memory = Memory::new(Box::new(mbc::RomOnly::new(vec![0x11; 0x1000], 0, false)));
+    memory.map_boot_rom(vec![0x22; 0x900], Model::Cgb).unwrap();
+    assert_eq!(memory.read(0x00FF), 0x22);
+    assert_eq!(memory.read(0x0100), 0x11);
+    assert_eq!(memory.read(0x01FF), 0x11);
+    assert_eq!(memory.read(0x0200), 0x22);
+    assert_eq!(memory.read(0x08FF), 0x22);
+    assert_eq!(memory.read(0x0900), 0x11);
+}
+
+#[test]
+fn post_boot_io_registers() {
+    let mut memory = Memory::from_rom(vec![]).unwrap();
+    memory.skip_boot_rom(Model::Dmg);
+    assert_eq!(memory.read(0xFF40), 0x91);
+    assert_eq!(memory.read(0xFF0F), 0xE1);
+    assert_eq!(memory.read(0xFF04), 0xAB);
+    assert_eq!(memory.read(0xFF07), 0xF8);
+    assert_eq!(memory.read(0xFFFF), 0x00);
+
+    memory.skip_boot_rom(Model::Dmg0);
+    assert_eq!(memory.read(0xFF04), 0x18);
+}
+
+#[test]
+fn timer_interrupt() {
+    let mut memory = Memory::from_rom(vec![]).unwrap();
+    memory.write(0xFF05, 0xFF);
+    memory.write(0xFF07, 0x05);
+
+    memory.tick(4);
+    assert_eq!(memory.read(IF_ADDRESS), 0xE0);
+    memory.tick(1);
+    assert_eq!(memory.read(IF_ADDRESS), 0xE4);
+}
+
+#[test]
+fn joypad_interrupt() {
+    let mut memory = Memory::from_rom(vec![]).unwrap();
+    memory.write(P1_ADDRESS, 0x20);
+    memory.set_buttons(Buttons { right: true, ..Buttons::default() });
+
+    assert_eq!(memory.read(P1_ADDRESS), 0xEE);
+    assert_eq!(memory.read(IF_ADDRESS), 0xF0);
+}
+
+#[test]
+fn serial_interrupt() {
+    let mut memory = Memory::from_rom(vec![]).unwrap();
+    memory.write(SB_ADDRESS, b'!');
+    memory.write(SC_ADDRESS, 0x81);
+
+    memory.tick(1023);
+    assert_eq!(memory.read(IF_ADDRESS), 0xE0);
+    memory.tick(1);
+    assert_eq!(memory.read(IF_ADDRESS), 0xE8);
+    assert_eq!(memory.serial_output(), b"!");
+}
+
+#[test]
+fn lcd_interrupts() {
+    let mut memory = Memory::from_rom(vec![]).unwrap();
+    memory.write(0xFF41, 0x40);
+    memory.write(0xFF45, 0x01);
+    memory.write(0xFF40, 0x91);
+
+    memory.tick(114);
+    assert_eq!(memory.read(0xFF44), 1);
+    assert_eq!(memory.read(IF_ADDRESS), 0xE2);
+
+    memory.tick(114 * 143);
+    assert_eq!(memory.read(0xFF44), 144);
+    assert_eq!(memory.read(IF_ADDRESS), 0xE3);
+}
@@ -0,0 +1,82 @@
+use cartridge::{parse_header, CgbSupport};
+
+/// The Game Boy hardware being emulated. Models differ in what their
+/// boot ROMs leave in the registers, which games use to tell them
+/// apart.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Model {
+    /// The original Game Boy with the early boot ROM.
+    Dmg0,
+    Dmg,
+    /// Game Boy Pocket.
+    Mgb,
+    /// Super Game Boy.
+    Sgb,
+    Sgb2,
+    /// Game Boy Color.
+    Cgb,
+    /// Game Boy Advance.
+    Agb,
+}
+
+impl Model {
+    /// Parse a model name as given on the command line, e.g. "dmg".
+    pub fn from_name(name: &str) -> Option<Model> {
+        match name.to_lowercase().as_str() {
+            "dmg0" => Some(Model::Dmg0),
+            "dmg" => Some(Model::Dmg),
+            "mgb" => Some(Model::Mgb),
+            "sgb" => Some(Model::Sgb),
+            "sgb2" => Some(Model::Sgb2),
+            "cgb" => Some(Model::Cgb),
+            "agb" => Some(Model::Agb),
+            _ => None,
+        }
+    }
+
+    /// Pick the model best suited to a ROM, based on the CGB and SGB
+    /// flags in its header. ROMs without a header run on a DMG.
+    pub fn detect(rom: &[u8]) -> Model {
+        match parse_header(rom) {
+            Ok(ref header) if header.cgb != CgbSupport::None => Model::Cgb,
+            Ok(ref header) if header.sgb => Model::Sgb,
+            _ => Model::Dmg,
+        }
+    }
+
+    /// Models with Game Boy Color hardware.
+    pub fn is_cgb(self) -> bool {
+        self == Model::Cgb || self == Model::Agb
+    }
+
+    /// The CGB boot ROM is mapped at 0000-00FF and 0200-08FF, the
+    /// others only at 0000-00FF.
+    pub fn boot_rom_size(self) -> usize {
+        if self.is_cgb() { 0x900 } else { 0x100 }
+    }
+}
+
+#[cfg(test)]
+fn rom_with_flags(cgb: u8, sgb: u8) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x143] = cgb;
+    rom[0x146] = sgb;
+    rom
+}
+
+#[test]
+fn model_names() {
+    assert_eq!(Model::from_name("dmg0"), Some(Model::Dmg0));
+    assert_eq!(Model::from_name("SGB2"), Some(Model::Sgb2));
+    assert_eq!(Model::from_name("agb"), Some(Model::Agb));
+    assert_eq!(Model::from_name("gba"), None);
+}
+
+#[test]
+fn detect_model() {
+    assert_eq!(Model::detect(&rom_with_flags(0x00, 0x00)), Model::Dmg);
+    assert_eq!(Model::detect(&rom_with_flags(0x00, 0x03)), Model::Sgb);
+    assert_eq!(Model::detect(&rom_with_flags(0x80, 0x03)), Model::Cgb);
+    assert_eq!(Model::detect(&rom_with_flags(0xC0, 0x00)), Model::Cgb);
+    assert_eq!(Model::detect(&[0x00]), Model::Dmg);
+}
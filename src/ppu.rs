@@ -0,0 +1,665 @@
+pub const LCDC_ADDRESS: u16 = 0xFF40;
+pub const STAT_ADDRESS: u16 = 0xFF41;
+pub const SCY_ADDRESS: u16 = 0xFF42;
+pub const SCX_ADDRESS: u16 = 0xFF43;
+pub const LY_ADDRESS: u16 = 0xFF44;
+pub const LYC_ADDRESS: u16 = 0xFF45;
+pub const BGP_ADDRESS: u16 = 0xFF47;
+pub const OBP0_ADDRESS: u16 = 0xFF48;
+pub const OBP1_ADDRESS: u16 = 0xFF49;
+pub const WY_ADDRESS: u16 = 0xFF4A;
+pub const WX_ADDRESS: u16 = 0xFF4B;
+
+/// Whether an I/O address is one of the LCD registers. FF46 is OAM
+/// DMA, which isn't part of the PPU.
+pub fn is_register(address: u16) -> bool {
+    (LCDC_ADDRESS..=WX_ADDRESS).contains(&address) && address != 0xFF46
+}
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+// LCDC bits.
+const LCD_ENABLE: u8 = 0x80;
+const WINDOW_TILE_MAP: u8 = 0x40;
+const WINDOW_ENABLE: u8 = 0x20;
+const TILE_DATA: u8 = 0x10;
+const BG_TILE_MAP: u8 = 0x08;
+const BG_ENABLE: u8 = 0x01;
+
+// STAT interrupt selects.
+const STAT_LYC: u8 = 0x40;
+const STAT_OAM_SCAN: u8 = 0x20;
+const STAT_VBLANK: u8 = 0x10;
+const STAT_HBLANK: u8 = 0x08;
+const STAT_COINCIDENCE: u8 = 0x04;
+
+/// Each line takes 456 dots (T-cycles).
+const DOTS_PER_LINE: u32 = 456;
+const OAM_SCAN_DOTS: u32 = 80;
+/// Pixel transfer can take longer when there are sprites or the
+/// window, but this is its minimum length.
+const TRANSFER_DOTS: u32 = 172;
+const VISIBLE_LINES: u8 = 144;
+const LINES_PER_FRAME: u8 = 154;
+
+/// What the PPU is doing. The number is what STAT reports.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Transfer = 3,
+}
+
+/// Interrupts requested by the PPU.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default)]
+pub struct PpuInterrupts {
+    pub vblank: bool,
+    pub stat: bool,
+}
+
+/// The picture processing unit, which owns video RAM, OAM and the LCD
+/// registers.
+///
+/// Visible lines go through OAM scan (80 dots), pixel transfer (172
+/// dots) and HBlank (the rest of the 456 dots). Lines 144-153 are
+/// VBlank. When the LCD is turned off, LY stays at 0 and nothing
+/// happens until it's turned on again.
+///
+/// Each line is drawn when pixel transfer finishes, and the finished
+/// frame is available from VBlank onwards. Pixels are shades from 0
+/// (white) to 3 (black), after applying the BGP palette.
+pub struct Ppu {
+    vram: [u8; 0x2000],
+    oam: [u8; 0xA0],
+
+    lcdc: u8,
+    /// The interrupt select bits of STAT. The rest are read only.
+    stat_select: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+
+    mode: Mode,
+    /// Dots into the current line.
+    dot: u32,
+    /// The STAT interrupt is requested when any selected condition
+    /// becomes true, so we track whether any of them held.
+    stat_line: bool,
+
+    /// The window starts once LY has matched WY during a frame.
+    window_triggered: bool,
+    /// Which line of the window is drawn next. This only advances on
+    /// lines where the window was visible.
+    window_line: u8,
+
+    /// The frame being drawn.
+    screen: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+    /// The last complete frame.
+    frame: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+    frame_count: u64,
+}
+
+impl Default for Ppu {
+    fn default() -> Ppu {
+        Ppu::new()
+    }
+}
+
+impl Ppu {
+    pub fn new() -> Ppu {
+        Ppu {
+            vram: [0; 0x2000],
+            oam: [0; 0xA0],
+            lcdc: 0,
+            stat_select: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            mode: Mode::HBlank,
+            dot: 0,
+            stat_line: false,
+            window_triggered: false,
+            window_line: 0,
+            screen: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_count: 0,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// The last complete frame, SCREEN_WIDTH pixels per row.
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+
+    /// How many frames have been completed.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn read_vram(&self, address: u16) -> u8 {
+        self.vram[address as usize - 0x8000]
+    }
+
+    pub fn write_vram(&mut self, address: u16, value: u8) {
+        self.vram[address as usize - 0x8000] = value;
+    }
+
+    pub fn read_oam(&self, address: u16) -> u8 {
+        self.oam[address as usize - 0xFE00]
+    }
+
+    pub fn write_oam(&mut self, address: u16, value: u8) {
+        self.oam[address as usize - 0xFE00] = value;
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            LCDC_ADDRESS => self.lcdc,
+            STAT_ADDRESS => {
+                // Bit 7 is unused. With the LCD off, the mode reads as 0.
+                let mode = if self.lcd_enabled() { self.mode as u8 } else { 0 };
+                let coincidence = if self.ly == self.lyc { STAT_COINCIDENCE } else { 0 };
+                0x80 | self.stat_select | coincidence | mode
+            }
+            SCY_ADDRESS => self.scy,
+            SCX_ADDRESS => self.scx,
+            LY_ADDRESS => self.ly,
+            LYC_ADDRESS => self.lyc,
+            BGP_ADDRESS => self.bgp,
+            OBP0_ADDRESS => self.obp0,
+            OBP1_ADDRESS => self.obp1,
+            WY_ADDRESS => self.wy,
+            _ => self.wx,
+        }
+    }
+
+    /// Write an LCD register. Returns true if the STAT interrupt should
+    /// be requested, which can happen when changing STAT or LYC.
+    pub fn write(&mut self, address: u16, value: u8) -> bool {
+        match address {
+            LCDC_ADDRESS => self.write_lcdc(value),
+            STAT_ADDRESS => {
+                self.stat_select = value & (STAT_LYC | STAT_OAM_SCAN | STAT_VBLANK | STAT_HBLANK)
+            }
+            SCY_ADDRESS => self.scy = value,
+            SCX_ADDRESS => self.scx = value,
+            // LY is read only.
+            LY_ADDRESS => {}
+            LYC_ADDRESS => self.lyc = value,
+            BGP_ADDRESS => self.bgp = value,
+            OBP0_ADDRESS => self.obp0 = value,
+            OBP1_ADDRESS => self.obp1 = value,
+            WY_ADDRESS => self.wy = value,
+            _ => self.wx = value,
+        }
+        self.update_stat_line()
+    }
+
+    fn write_lcdc(&mut self, value: u8) {
+        let was_enabled = self.lcd_enabled();
+        self.lcdc = value;
+
+        if was_enabled && !self.lcd_enabled() {
+            self.ly = 0;
+            self.dot = 0;
+            self.mode = Mode::HBlank;
+        } else if !was_enabled && self.lcd_enabled() {
+            self.ly = 0;
+            self.dot = 0;
+            self.mode = Mode::OamScan;
+            self.start_frame();
+        }
+    }
+
+    fn lcd_enabled(&self) -> bool {
+        self.lcdc & LCD_ENABLE != 0
+    }
+
+    /// Advance by one M-cycle, which is four dots.
+    pub fn tick(&mut self) -> PpuInterrupts {
+        let mut interrupts = PpuInterrupts::default();
+        if !self.lcd_enabled() {
+            return interrupts;
+        }
+
+        // Every mode boundary is a multiple of four dots, so we can
+        // step a whole M-cycle at a time.
+        self.dot += 4;
+        if self.dot == DOTS_PER_LINE {
+            self.dot = 0;
+            self.ly = (self.ly + 1) % LINES_PER_FRAME;
+            if self.ly == VISIBLE_LINES {
+                self.mode = Mode::VBlank;
+                self.frame = self.screen;
+                self.frame_count += 1;
+                interrupts.vblank = true;
+            } else if self.ly < VISIBLE_LINES {
+                if self.ly == 0 {
+                    self.start_frame();
+                } else {
+                    self.start_line();
+                }
+            }
+        } else if self.ly < VISIBLE_LINES {
+            if self.dot == OAM_SCAN_DOTS {
+                self.mode = Mode::Transfer;
+            } else if self.dot == OAM_SCAN_DOTS + TRANSFER_DOTS {
+                self.mode = Mode::HBlank;
+                self.render_line();
+            }
+        }
+
+        interrupts.stat = self.update_stat_line();
+        interrupts
+    }
+
+    fn start_frame(&mut self) {
+        self.window_triggered = false;
+        self.window_line = 0;
+        self.start_line();
+    }
+
+    fn start_line(&mut self) {
+        self.mode = Mode::OamScan;
+        if self.ly == self.wy {
+            self.window_triggered = true;
+        }
+    }
+
+    /// Draw the background and window for the current line.
+    fn render_line(&mut self) {
+        let row = self.ly as usize * SCREEN_WIDTH;
+
+        // On DMG, clearing this bit blanks both layers.
+        if self.lcdc & BG_ENABLE == 0 {
+            for pixel in &mut self.screen[row..row + SCREEN_WIDTH] {
+                *pixel = 0;
+            }
+            return;
+        }
+
+        // WX is the window's left edge plus 7.
+        let window = self.lcdc & WINDOW_ENABLE != 0 && self.window_triggered && self.wx <= 166;
+        let bg_map = if self.lcdc & BG_TILE_MAP != 0 { 0x9C00 } else { 0x9800 };
+        let window_map = if self.lcdc & WINDOW_TILE_MAP != 0 { 0x9C00 } else { 0x9800 };
+
+        for x in 0..SCREEN_WIDTH as u8 {
+            let color = if window && x as u16 + 7 >= self.wx as u16 {
+                self.tile_pixel(window_map, x + 7 - self.wx, self.window_line)
+            } else {
+                self.tile_pixel(bg_map, x.wrapping_add(self.scx), self.ly.wrapping_add(self.scy))
+            };
+            self.screen[row + x as usize] = (self.bgp >> (color * 2)) & 0x03;
+        }
+
+        if window {
+            self.window_line += 1;
+        }
+    }
+
+    /// The color number (before the palette) at a position in a 256x256
+    /// tile map.
+    fn tile_pixel(&self, map: u16, x: u8, y: u8) -> u8 {
+        let map_address = map + (y as u16 / 8) * 32 + x as u16 / 8;
+        let tile = self.read_vram(map_address);
+
+        // Tiles are 16 bytes. With the 8800 addressing mode, the
+        // index is signed and relative to 9000.
+        let tile_address = if self.lcdc & TILE_DATA != 0 {
+            0x8000 + tile as u16 * 16
+        } else {
+            (0x9000 + tile as i8 as i32 * 16) as u16
+        };
+
+        // Each row is two bytes, holding the low and high bits of the
+        // color for each pixel, with the leftmost pixel in bit 7.
+        let row_address = tile_address + (y as u16 % 8) * 2;
+        let low = self.read_vram(row_address);
+        let high = self.read_vram(row_address + 1);
+        let bit = 7 - x % 8;
+        ((high >> bit) & 1) << 1 | (low >> bit) & 1
+    }
+
+    /// Recompute the STAT interrupt line. Returns true on a rising
+    /// edge, when the interrupt should be requested.
+    fn update_stat_line(&mut self) -> bool {
+        let line = self.lcd_enabled() && (
+            (self.stat_select & STAT_LYC != 0 && self.ly == self.lyc) ||
+            (self.stat_select & STAT_OAM_SCAN != 0 && self.mode == Mode::OamScan) ||
+            (self.stat_select & STAT_VBLANK != 0 && self.mode == Mode::VBlank) ||
+            (self.stat_select & STAT_HBLANK != 0 && self.mode == Mode::HBlank));
+
+        let rising = line && !self.stat_line;
+        self.stat_line = line;
+        rising
+    }
+}
+
+#[cfg(test)]
+fn ppu_with_lcd_on() -> Ppu {
+    let mut ppu = Ppu::new();
+    ppu.write(LCDC_ADDRESS, 0x91);
+    ppu
+}
+
+// Tick for the given number of M-cycles, counting interrupts.
+#[cfg(test)]
+fn tick_cycles(ppu: &mut Ppu, cycles: u32) -> (u32, u32) {
+    let mut vblank = 0;
+    let mut stat = 0;
+    for _ in 0..cycles {
+        let interrupts = ppu.tick();
+        vblank += interrupts.vblank as u32;
+        stat += interrupts.stat as u32;
+    }
+    (vblank, stat)
+}
+
+#[test]
+fn mode_timings() {
+    let mut ppu = ppu_with_lcd_on();
+    assert_eq!(ppu.mode(), Mode::OamScan);
+
+    tick_cycles(&mut ppu, 19);
+    assert_eq!(ppu.mode(), Mode::OamScan);
+    tick_cycles(&mut ppu, 1);
+    assert_eq!(ppu.mode(), Mode::Transfer);
+
+    tick_cycles(&mut ppu, 42);
+    assert_eq!(ppu.mode(), Mode::Transfer);
+    tick_cycles(&mut ppu, 1);
+    assert_eq!(ppu.mode(), Mode::HBlank);
+    assert_eq!(ppu.read(STAT_ADDRESS) & 0x03, 0);
+
+    tick_cycles(&mut ppu, 50);
+    assert_eq!(ppu.mode(), Mode::HBlank);
+    assert_eq!(ppu.read(LY_ADDRESS), 0);
+    tick_cycles(&mut ppu, 1);
+    assert_eq!(ppu.mode(), Mode::OamScan);
+    assert_eq!(ppu.read(LY_ADDRESS), 1);
+}
+
+#[test]
+fn vblank() {
+    let mut ppu = ppu_with_lcd_on();
+    let (vblank, _) = tick_cycles(&mut ppu, 114 * 144 - 1);
+    assert_eq!(vblank, 0);
+    assert_eq!(ppu.read(LY_ADDRESS), 143);
+
+    let (vblank, _) = tick_cycles(&mut ppu, 1);
+    assert_eq!(vblank, 1);
+    assert_eq!(ppu.read(LY_ADDRESS), 144);
+    assert_eq!(ppu.mode(), Mode::VBlank);
+
+    tick_cycles(&mut ppu, 114 * 9);
+    assert_eq!(ppu.read(LY_ADDRESS), 153);
+    assert_eq!(ppu.mode(), Mode::VBlank);
+
+    tick_cycles(&mut ppu, 114);
+    assert_eq!(ppu.read(LY_ADDRESS), 0);
+    assert_eq!(ppu.mode(), Mode::OamScan);
+}
+
+#[test]
+fn one_vblank_per_frame() {
+    let mut ppu = ppu_with_lcd_on();
+    let (vblank, stat) = tick_cycles(&mut ppu, 17556 * 3);
+    assert_eq!(vblank, 3);
+    assert_eq!(stat, 0);
+}
+
+#[test]
+fn lyc_interrupt() {
+    let mut ppu = ppu_with_lcd_on();
+    ppu.write(LYC_ADDRESS, 2);
+    ppu.write(STAT_ADDRESS, STAT_LYC);
+    assert_eq!(ppu.read(STAT_ADDRESS) & STAT_COINCIDENCE, 0);
+
+    let (_, stat) = tick_cycles(&mut ppu, 114 * 2);
+    assert_eq!(stat, 1);
+    assert_eq!(ppu.read(STAT_ADDRESS), 0x80 | STAT_LYC | STAT_COINCIDENCE | 2);
+
+    // Once per frame.
+    let (_, stat) = tick_cycles(&mut ppu, 17556);
+    assert_eq!(stat, 1);
+}
+
+#[test]
+fn hblank_interrupt() {
+    let mut ppu = ppu_with_lcd_on();
+    ppu.write(STAT_ADDRESS, STAT_HBLANK);
+    let (_, stat) = tick_cycles(&mut ppu, 114 * 144);
+    assert_eq!(stat, 144);
+}
+
+#[test]
+fn stat_interrupt_on_rising_edge_only() {
+    let mut ppu = ppu_with_lcd_on();
+    // The VBlank condition holds for all of lines 144-153, and LYC
+    // matching during it doesn't cause another interrupt.
+    ppu.write(LYC_ADDRESS, 150);
+    ppu.write(STAT_ADDRESS, STAT_VBLANK | STAT_LYC);
+    let (_, stat) = tick_cycles(&mut ppu, 17556);
+    assert_eq!(stat, 1);
+}
+
+#[test]
+fn stat_write_can_interrupt() {
+    let mut ppu = ppu_with_lcd_on();
+    tick_cycles(&mut ppu, 114 * 144);
+    assert!(ppu.write(STAT_ADDRESS, STAT_VBLANK));
+    assert!(!ppu.write(STAT_ADDRESS, STAT_VBLANK));
+}
+
+#[test]
+fn lcd_off() {
+    let mut ppu = ppu_with_lcd_on();
+    ppu.write(STAT_ADDRESS, STAT_HBLANK | STAT_OAM_SCAN);
+    tick_cycles(&mut ppu, 114 * 10 + 30);
+
+    ppu.write(LCDC_ADDRESS, 0x11);
+    assert_eq!(ppu.read(LY_ADDRESS), 0);
+    assert_eq!(ppu.read(STAT_ADDRESS) & 0x03, 0);
+    assert_eq!(tick_cycles(&mut ppu, 17556 * 2), (0, 0));
+    assert_eq!(ppu.read(LY_ADDRESS), 0);
+
+    // Turning it back on starts a new frame.
+    ppu.write(LCDC_ADDRESS, 0x91);
+    assert_eq!(ppu.mode(), Mode::OamScan);
+    tick_cycles(&mut ppu, 114);
+    assert_eq!(ppu.read(LY_ADDRESS), 1);
+}
+
+#[test]
+fn ly_is_read_only() {
+    let mut ppu = ppu_with_lcd_on();
+    tick_cycles(&mut ppu, 114 * 5);
+    ppu.write(LY_ADDRESS, 0x42);
+    assert_eq!(ppu.read(LY_ADDRESS), 5);
+}
+
+// Run until the frame started now is complete.
+#[cfg(test)]
+fn render_frame(ppu: &mut Ppu) {
+    let frames = ppu.frame_count();
+    while ppu.frame_count() == frames {
+        ppu.tick();
+    }
+}
+
+#[cfg(test)]
+fn pixel(ppu: &Ppu, x: usize, y: usize) -> u8 {
+    ppu.frame()[y * SCREEN_WIDTH + x]
+}
+
+// Fill a tile's rows with the given color number.
+#[cfg(test)]
+fn solid_tile(ppu: &mut Ppu, address: u16, color: u8) {
+    for row in 0..8 {
+        ppu.write_vram(address + row * 2, if color & 1 != 0 { 0xFF } else { 0x00 });
+        ppu.write_vram(address + row * 2 + 1, if color & 2 != 0 { 0xFF } else { 0x00 });
+    }
+}
+
+#[test]
+fn background_tile() {
+    let mut ppu = Ppu::new();
+    // Rows alternate between color 1 on the left half and color 2 on
+    // the right half.
+    for row in 0..8 {
+        ppu.write_vram(0x8010 + row * 2, 0xF0);
+        ppu.write_vram(0x8010 + row * 2 + 1, 0x0F);
+    }
+    ppu.write_vram(0x9800, 1);
+    ppu.write(BGP_ADDRESS, 0xE4);
+    ppu.write(LCDC_ADDRESS, LCD_ENABLE | TILE_DATA | BG_ENABLE);
+    render_frame(&mut ppu);
+
+    assert_eq!(ppu.frame().len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+    assert_eq!(pixel(&ppu, 0, 0), 1);
+    assert_eq!(pixel(&ppu, 3, 7), 1);
+    assert_eq!(pixel(&ppu, 4, 0), 2);
+    assert_eq!(pixel(&ppu, 8, 0), 0);
+    assert_eq!(pixel(&ppu, 0, 8), 0);
+}
+
+#[test]
+fn palette() {
+    let mut ppu = Ppu::new();
+    solid_tile(&mut ppu, 0x8000, 1);
+    ppu.write(BGP_ADDRESS, 0x0C);
+    ppu.write(LCDC_ADDRESS, LCD_ENABLE | TILE_DATA | BG_ENABLE);
+    render_frame(&mut ppu);
+    assert_eq!(pixel(&ppu, 0, 0), 3);
+}
+
+#[test]
+fn signed_tile_data() {
+    let mut ppu = Ppu::new();
+    // Tile 0x80 is at 8800, and tile 0 is at 9000.
+    solid_tile(&mut ppu, 0x8800, 3);
+    solid_tile(&mut ppu, 0x9000, 2);
+    ppu.write_vram(0x9800, 0x80);
+    ppu.write(BGP_ADDRESS, 0xE4);
+    ppu.write(LCDC_ADDRESS, LCD_ENABLE | BG_ENABLE);
+    render_frame(&mut ppu);
+
+    assert_eq!(pixel(&ppu, 0, 0), 3);
+    assert_eq!(pixel(&ppu, 8, 0), 2);
+}
+
+#[test]
+fn background_tile_map_select() {
+    let mut ppu = Ppu::new();
+    solid_tile(&mut ppu, 0x8010, 3);
+    ppu.write_vram(0x9C00, 1);
+    ppu.write(BGP_ADDRESS, 0xE4);
+    ppu.write(LCDC_ADDRESS, LCD_ENABLE | TILE_DATA | BG_ENABLE);
+    render_frame(&mut ppu);
+    assert_eq!(pixel(&ppu, 0, 0), 0);
+
+    ppu.write(LCDC_ADDRESS, LCD_ENABLE | TILE_DATA | BG_TILE_MAP | BG_ENABLE);
+    render_frame(&mut ppu);
+    assert_eq!(pixel(&ppu, 0, 0), 3);
+}
+
+#[test]
+fn scrolling() {
+    let mut ppu = Ppu::new();
+    solid_tile(&mut ppu, 0x8010, 3);
+    // The tile at (31, 31) in the map wraps around to the top left.
+    ppu.write_vram(0x9800 + 31 * 32 + 31, 1);
+    ppu.write(BGP_ADDRESS, 0xE4);
+    ppu.write(SCX_ADDRESS, 252);
+    ppu.write(SCY_ADDRESS, 250);
+    ppu.write(LCDC_ADDRESS, LCD_ENABLE | TILE_DATA | BG_ENABLE);
+    render_frame(&mut ppu);
+
+    assert_eq!(pixel(&ppu, 3, 5), 3);
+    assert_eq!(pixel(&ppu, 4, 5), 0);
+    assert_eq!(pixel(&ppu, 3, 6), 0);
+}
+
+#[test]
+fn background_disabled() {
+    let mut ppu = Ppu::new();
+    solid_tile(&mut ppu, 0x8000, 3);
+    ppu.write(BGP_ADDRESS, 0xE4);
+    ppu.write(LCDC_ADDRESS, LCD_ENABLE | TILE_DATA | WINDOW_ENABLE);
+    render_frame(&mut ppu);
+    assert_eq!(pixel(&ppu, 0, 0), 0);
+}
+
+#[test]
+fn window() {
+    let mut ppu = Ppu::new();
+    solid_tile(&mut ppu, 0x8010, 3);
+    for i in 0..0x400 {
+        ppu.write_vram(0x9C00 + i, 1);
+    }
+    ppu.write(BGP_ADDRESS, 0xE4);
+    ppu.write(WY_ADDRESS, 10);
+    ppu.write(WX_ADDRESS, 27);
+    ppu.write(LCDC_ADDRESS,
+              LCD_ENABLE | WINDOW_TILE_MAP | WINDOW_ENABLE | TILE_DATA | BG_ENABLE);
+    render_frame(&mut ppu);
+
+    assert_eq!(pixel(&ppu, 20, 9), 0);
+    assert_eq!(pixel(&ppu, 19, 10), 0);
+    assert_eq!(pixel(&ppu, 20, 10), 3);
+    assert_eq!(pixel(&ppu, 159, 143), 3);
+
+    // Without the enable bit, only the background is drawn.
+    ppu.write(LCDC_ADDRESS, LCD_ENABLE | WINDOW_TILE_MAP | TILE_DATA | BG_ENABLE);
+    render_frame(&mut ppu);
+    assert_eq!(pixel(&ppu, 20, 10), 0);
+}
+
+#[test]
+fn window_line_counter() {
+    let mut ppu = Ppu::new();
+    // Window rows 0-7 use tile 1, and rows 8-15 use tile 2.
+    solid_tile(&mut ppu, 0x8010, 1);
+    solid_tile(&mut ppu, 0x8020, 2);
+    for i in 0..32 {
+        ppu.write_vram(0x9C00 + i, 1);
+        ppu.write_vram(0x9C20 + i, 2);
+    }
+    ppu.write(BGP_ADDRESS, 0xE4);
+    ppu.write(WX_ADDRESS, 7);
+    ppu.write(LCDC_ADDRESS,
+              LCD_ENABLE | WINDOW_TILE_MAP | WINDOW_ENABLE | TILE_DATA | BG_ENABLE);
+
+    // Hide the window for lines 4-9, part way through the frame.
+    tick_cycles(&mut ppu, 114 * 4);
+    ppu.write(WX_ADDRESS, 200);
+    tick_cycles(&mut ppu, 114 * 6);
+    ppu.write(WX_ADDRESS, 7);
+    render_frame(&mut ppu);
+
+    assert_eq!(pixel(&ppu, 0, 3), 1);
+    // The background shows through while the window is hidden.
+    assert_eq!(pixel(&ppu, 0, 4), 0);
+    // The window carries on from its fifth line, not the eleventh.
+    assert_eq!(pixel(&ppu, 0, 10), 1);
+    assert_eq!(pixel(&ppu, 0, 13), 1);
+    assert_eq!(pixel(&ppu, 0, 14), 2);
+}
@@ -0,0 +1,162 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use memory::Memory;
+
+/// Where battery-backed RAM for a ROM is kept: next to the ROM, with
+/// a `.sav` extension, as other emulators do.
+pub fn save_path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("sav")
+}
+
+/// A save file for battery-backed cartridge RAM. The file holds the
+/// raw contents of RAM, followed by the clock state for MBC3 carts
+/// with a timer.
+pub struct SaveFile {
+    path: PathBuf,
+    /// What's currently on disk, so unchanged RAM isn't rewritten.
+    last_saved: Option<Vec<u8>>,
+}
+
+impl SaveFile {
+    pub fn new(path: PathBuf) -> SaveFile {
+        SaveFile {
+            path,
+            last_saved: None,
+        }
+    }
+
+    /// Load the save file into cartridge RAM. It's fine for the file
+    /// not to exist yet. Cartridges without a battery ignore it, since
+    /// their RAM wouldn't have survived being switched off.
+    pub fn load(&mut self, memory: &mut Memory) -> Result<(), String> {
+        if memory.save_data().is_none() {
+            return Ok(());
+        }
+
+        match fs::read(&self.path) {
+            Ok(data) => {
+                memory.load_save_data(&data);
+                self.last_saved = Some(data);
+                Ok(())
+            }
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format!("Could not read save file {}: {}", self.path.display(), e)),
+        }
+    }
+
+    /// Write cartridge RAM to the save file, if the cartridge has a
+    /// battery and RAM has changed since the last save.
+    pub fn save(&mut self, memory: &Memory) -> Result<(), String> {
+        let data = match memory.save_data() {
+            Some(data) => data,
+            None => return Ok(()),
+        };
+        if self.last_saved.as_ref() == Some(&data) {
+            return Ok(());
+        }
+
+        // Write to a temporary file first, so a crash part way
+        // through doesn't leave a truncated save.
+        let temp_path = self.path.with_extension("sav.tmp");
+        fs::write(&temp_path, &data)
+            .and_then(|_| fs::rename(&temp_path, &self.path))
+            .map_err(|e| format!("Could not write save file {}: {}", self.path.display(), e))?;
+
+        self.last_saved = Some(data);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+use mbc::RomOnly;
+
+#[cfg(test)]
+fn temp_save_path(name: &str) -> PathBuf {
+    let path = ::std::env::temp_dir().join(format!("gameboy_emulator_{}_{}.sav",
+                                                     name, ::std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn save_path_replaces_extension() {
+    assert_eq!(save_path(Path::new("/roms/tetris.gb")), PathBuf::from("/roms/tetris.sav"));
+    assert_eq!(save_path(Path::new("pokemon.gbc")), PathBuf::from("pokemon.sav"));
+}
+
+#[test]
+fn save_round_trip() {
+    let path = temp_save_path("round_trip");
+
+    let mut memory = Memory::new(Box::new(RomOnly::new(vec![], 0x2000, true)));
+    memory.write(0xA000, 0x42);
+    memory.write(0xBFFF, 0x24);
+    SaveFile::new(path.clone()).save(&memory).unwrap();
+
+    let data = fs::read(&path).unwrap();
+    assert_eq!(data.len(), 0x2000);
+    assert_eq!(data[0], 0x42);
+
+    let mut restored = Memory::new(Box::new(RomOnly::new(vec![], 0x2000, true)));
+    SaveFile::new(path.clone()).load(&mut restored).unwrap();
+    assert_eq!(restored.read(0xA000), 0x42);
+    assert_eq!(restored.read(0xBFFF), 0x24);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn missing_save_file() {
+    let path = temp_save_path("missing");
+    let mut memory = Memory::new(Box::new(RomOnly::new(vec![], 0x2000, true)));
+    assert!(SaveFile::new(path).load(&mut memory).is_ok());
+}
+
+#[test]
+fn no_save_without_battery() {
+    let path = temp_save_path("no_battery");
+    let mut memory = Memory::new(Box::new(RomOnly::new(vec![], 0x2000, false)));
+    memory.write(0xA000, 0x42);
+
+    SaveFile::new(path.clone()).save(&memory).unwrap();
+    assert!(!path.exists());
+}
+
+#[test]
+fn no_load_without_battery() {
+    let path = temp_save_path("no_load_battery");
+    fs::write(&path, vec![0x42; 0x2000]).unwrap();
+
+    let mut memory = Memory::new(Box::new(RomOnly::new(vec![], 0x2000, false)));
+    SaveFile::new(path.clone()).load(&mut memory).unwrap();
+    assert_eq!(memory.read(0xA000), 0x00);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn fetch_execute_saves_on_exit() {
+    use instructions::fetch_execute;
+    use model::Model;
+
+    let path = temp_save_path("fetch_execute");
+    let mut rom = vec![0; 0x8000];
+    let program = [
+        0x3E, 0x0A,       // LD A,$0A
+        0xEA, 0x00, 0x00, // LD ($0000),A ; enable RAM
+        0x3E, 0x42,       // LD A,$42
+        0xEA, 0x00, 0xA0, // LD ($A000),A
+        0x10, 0x00,       // STOP
+    ];
+    rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+    rom[0x147] = 0x03; // MBC1+RAM+BATTERY
+    rom[0x149] = 0x02; // 8 KiB
+
+    let mut save_file = SaveFile::new(path.clone());
+    fetch_execute(&rom, Model::Dmg, None, Some(&mut save_file)).unwrap();
+    assert_eq!(fs::read(&path).unwrap()[0], 0x42);
+
+    fs::remove_file(&path).unwrap();
+}
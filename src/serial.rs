@@ -0,0 +1,293 @@
+use std::io::Write;
+
+use link::Link;
+
+/// SB: the byte being sent, which is shifted out as the received
+/// byte is shifted in.
+pub const SB_ADDRESS: u16 = 0xFF01;
+/// SC: serial transfer control.
+pub const SC_ADDRESS: u16 = 0xFF02;
+
+const TRANSFER_START: u8 = 0x80;
+const INTERNAL_CLOCK: u8 = 0x01;
+
+/// The internal clock shifts one bit every 512 T-cycles (8192 Hz).
+const CYCLES_PER_BIT: u32 = 128;
+
+/// Where bytes sent over the serial port go.
+pub enum SerialOutput {
+    /// Keep them, for the caller to inspect.
+    Buffer(Vec<u8>),
+    /// Write them out as they're sent, e.g. to stdout.
+    Stream(Box<dyn Write>),
+}
+
+/// The serial port. With nothing connected, transfers using the
+/// internal clock still complete, and receive 0xFF. Transfers using
+/// an external clock never complete.
+///
+/// With a link cable, the byte is exchanged as soon as an internal
+/// clock transfer starts, and shows up in SB when the transfer
+/// finishes. Transfers using an external clock finish when the other
+/// side sends a byte.
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    /// Bits left to shift in the current transfer.
+    bits_remaining: u8,
+    cycles_until_shift: u32,
+    /// The byte being sent by the current transfer.
+    sending: u8,
+    /// The byte received from the link cable by the current
+    /// transfer.
+    receiving: Option<u8>,
+    output: SerialOutput,
+    link: Option<Box<dyn Link>>,
+    /// When to next check the link cable for incoming transfers.
+    cycles_until_poll: u32,
+}
+
+impl Default for Serial {
+    fn default() -> Serial {
+        Serial::new()
+    }
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            sb: 0,
+            sc: 0,
+            bits_remaining: 0,
+            cycles_until_shift: 0,
+            sending: 0,
+            receiving: None,
+            output: SerialOutput::Buffer(vec![]),
+            link: None,
+            cycles_until_poll: CYCLES_PER_BIT,
+        }
+    }
+
+    /// Plug in a link cable.
+    pub fn set_link(&mut self, link: Box<dyn Link>) {
+        self.link = Some(link);
+    }
+
+    pub fn set_output(&mut self, output: SerialOutput) {
+        self.output = output;
+    }
+
+    /// Bytes sent so far, if they're being kept in a buffer.
+    pub fn output(&self) -> &[u8] {
+        match self.output {
+            SerialOutput::Buffer(ref bytes) => bytes,
+            SerialOutput::Stream(_) => &[],
+        }
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        if address == SB_ADDRESS {
+            self.sb
+        } else {
+            // Bits 1-6 are unused.
+            self.sc | 0x7E
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        if address == SB_ADDRESS {
+            self.sb = value;
+            return;
+        }
+
+        self.sc = value & (TRANSFER_START | INTERNAL_CLOCK);
+        if self.sc == TRANSFER_START | INTERNAL_CLOCK {
+            self.bits_remaining = 8;
+            self.cycles_until_shift = CYCLES_PER_BIT;
+            self.sending = self.sb;
+            let sb = self.sb;
+            self.receiving = self.link.as_mut().map(|link| link.exchange(sb));
+        } else {
+            self.bits_remaining = 0;
+        }
+    }
+
+    /// Advance the serial clock by one M-cycle. Returns true if the
+    /// serial interrupt should be requested.
+    pub fn tick(&mut self) -> bool {
+        if self.link.is_some() {
+            // Checking the link every cycle would be slow, and the
+            // master can't clock bits any faster than this.
+            self.cycles_until_poll -= 1;
+            if self.cycles_until_poll == 0 {
+                self.cycles_until_poll = CYCLES_PER_BIT;
+                if self.poll_link() {
+                    return true;
+                }
+            }
+        }
+
+        if self.bits_remaining == 0 {
+            return false;
+        }
+        self.cycles_until_shift -= 1;
+        if self.cycles_until_shift > 0 {
+            return false;
+        }
+
+        // With nothing connected, the input line is pulled high.
+        self.sb = (self.sb << 1) | 1;
+        self.bits_remaining -= 1;
+        self.cycles_until_shift = CYCLES_PER_BIT;
+        if self.bits_remaining > 0 {
+            return false;
+        }
+
+        if let Some(byte) = self.receiving.take() {
+            self.sb = byte;
+        }
+        let byte = self.sending;
+        self.finish_transfer(byte);
+        true
+    }
+
+    /// Answer a transfer started by the other end of the link. The
+    /// master's clock shifts its byte into SB whether or not we're
+    /// expecting it, but the transfer only finishes (and interrupts)
+    /// if one was started with the external clock. Returns true if the
+    /// serial interrupt should be requested.
+    fn poll_link(&mut self) -> bool {
+        let sb = self.sb;
+        let received = match self.link.as_mut().and_then(|link| link.poll(sb)) {
+            Some(byte) => byte,
+            None => return false,
+        };
+
+        self.sb = received;
+        if self.sc != TRANSFER_START {
+            return false;
+        }
+        self.finish_transfer(sb);
+        true
+    }
+
+    fn finish_transfer(&mut self, byte: u8) {
+        self.sc &= !TRANSFER_START;
+        match self.output {
+            SerialOutput::Buffer(ref mut bytes) => bytes.push(byte),
+            SerialOutput::Stream(ref mut writer) => {
+                let _ = writer.write_all(&[byte]).and_then(|_| writer.flush());
+            }
+        }
+    }
+}
+
+// A link to a Game Boy that always sends the same byte, and
+// optionally starts a transfer when polled.
+#[cfg(test)]
+struct FakeLink {
+    reply: u8,
+    incoming: Option<u8>,
+}
+
+#[cfg(test)]
+impl Link for FakeLink {
+    fn exchange(&mut self, _: u8) -> u8 {
+        self.reply
+    }
+
+    fn poll(&mut self, _: u8) -> Option<u8> {
+        self.incoming.take()
+    }
+}
+
+#[cfg(test)]
+fn send(serial: &mut Serial, byte: u8) -> u32 {
+    serial.write(SB_ADDRESS, byte);
+    serial.write(SC_ADDRESS, TRANSFER_START | INTERNAL_CLOCK);
+    let mut cycles = 0;
+    while serial.read(SC_ADDRESS) & TRANSFER_START != 0 {
+        serial.tick();
+        cycles += 1;
+    }
+    cycles
+}
+
+#[test]
+fn transfer_timing() {
+    let mut serial = Serial::new();
+    serial.write(SB_ADDRESS, b'A');
+    serial.write(SC_ADDRESS, 0x81);
+
+    for _ in 0..8 * CYCLES_PER_BIT - 1 {
+        assert!(!serial.tick());
+    }
+    assert_eq!(serial.read(SC_ADDRESS), 0xFF);
+    assert!(serial.tick());
+    assert_eq!(serial.read(SC_ADDRESS), 0x7F);
+    assert!(!serial.tick());
+}
+
+#[test]
+fn transfer_receives_ff() {
+    let mut serial = Serial::new();
+    assert_eq!(send(&mut serial, 0x12), 1024);
+    assert_eq!(serial.read(SB_ADDRESS), 0xFF);
+}
+
+#[test]
+fn transfer_output_buffer() {
+    let mut serial = Serial::new();
+    for byte in b"Passed" {
+        send(&mut serial, *byte);
+    }
+    assert_eq!(serial.output(), b"Passed");
+}
+
+#[test]
+fn external_clock_never_completes() {
+    let mut serial = Serial::new();
+    serial.write(SB_ADDRESS, 0x12);
+    serial.write(SC_ADDRESS, TRANSFER_START);
+    for _ in 0..10000 {
+        assert!(!serial.tick());
+    }
+    assert_eq!(serial.read(SC_ADDRESS), 0xFE);
+    assert!(serial.output().is_empty());
+}
+
+#[test]
+fn link_master() {
+    let mut serial = Serial::new();
+    serial.set_link(Box::new(FakeLink { reply: 0x99, incoming: None }));
+    assert_eq!(send(&mut serial, 0x42), 1024);
+    assert_eq!(serial.read(SB_ADDRESS), 0x99);
+    assert_eq!(serial.output(), &[0x42]);
+}
+
+#[test]
+fn link_slave() {
+    let mut serial = Serial::new();
+    serial.set_link(Box::new(FakeLink { reply: 0x00, incoming: Some(0x99) }));
+    serial.write(SB_ADDRESS, 0x42);
+    serial.write(SC_ADDRESS, TRANSFER_START);
+
+    let interrupts = (0..CYCLES_PER_BIT).filter(|_| serial.tick()).count();
+    assert_eq!(interrupts, 1);
+    assert_eq!(serial.read(SB_ADDRESS), 0x99);
+    assert_eq!(serial.read(SC_ADDRESS), 0x7E);
+    assert_eq!(serial.output(), &[0x42]);
+}
+
+#[test]
+fn link_slave_not_ready() {
+    let mut serial = Serial::new();
+    serial.set_link(Box::new(FakeLink { reply: 0x00, incoming: Some(0x99) }));
+    serial.write(SB_ADDRESS, 0x42);
+
+    // The master's byte still arrives, but nothing finishes.
+    assert!(!(0..CYCLES_PER_BIT).any(|_| serial.tick()));
+    assert_eq!(serial.read(SB_ADDRESS), 0x99);
+    assert_eq!(serial.read(SC_ADDRESS), 0x7E);
+    assert!(serial.output().is_empty());
+}
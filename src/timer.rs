@@ -0,0 +1,250 @@
+/// DIV: the upper byte of the internal counter.
+pub const DIV_ADDRESS: u16 = 0xFF04;
+/// TIMA: the timer counter.
+pub const TIMA_ADDRESS: u16 = 0xFF05;
+/// TMA: the value TIMA is reloaded with when it overflows.
+pub const TMA_ADDRESS: u16 = 0xFF06;
+/// TAC: timer enable and frequency.
+pub const TAC_ADDRESS: u16 = 0xFF07;
+
+const TAC_ENABLE: u8 = 0x04;
+
+/// The timer. Everything is driven by a 16-bit counter that
+/// increments every T-cycle, and DIV is its upper byte.
+///
+/// TIMA increments when the counter bit selected by TAC goes from 1
+/// to 0, with that bit ANDed with the enable bit. Because of this,
+/// writing to DIV or TAC can increment TIMA when it clears the bit.
+///
+/// When TIMA overflows it reads as 0 for one M-cycle, and is then
+/// reloaded from TMA and requests an interrupt.
+#[derive(Debug, Default)]
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    /// TIMA overflowed on the last M-cycle, and is due to be
+    /// reloaded. Writing TIMA now cancels the reload.
+    overflowed: bool,
+    /// TIMA was reloaded on the last M-cycle. Writes to TIMA are
+    /// ignored, and writes to TMA also go to TIMA.
+    reloaded: bool,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer::default()
+    }
+
+    /// Set the internal counter directly, e.g. to the value the boot
+    /// ROM leaves it at.
+    pub fn set_counter(&mut self, counter: u16) {
+        self.counter = counter;
+    }
+
+    /// Advance the timer by one M-cycle. Returns true if the timer
+    /// interrupt should be requested.
+    pub fn tick(&mut self) -> bool {
+        self.reloaded = false;
+        let interrupt = if self.overflowed {
+            self.overflowed = false;
+            self.reloaded = true;
+            self.tima = self.tma;
+            true
+        } else {
+            false
+        };
+
+        let before = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+        if before && !self.signal() {
+            self.increment();
+        }
+
+        interrupt
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            DIV_ADDRESS => (self.counter >> 8) as u8,
+            TIMA_ADDRESS => self.tima,
+            TMA_ADDRESS => self.tma,
+            // Only the lower three bits of TAC exist.
+            _ => self.tac | 0xF8,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        let before = self.signal();
+        match address {
+            DIV_ADDRESS => self.counter = 0,
+            TIMA_ADDRESS => {
+                if !self.reloaded {
+                    self.tima = value;
+                    self.overflowed = false;
+                }
+            }
+            TMA_ADDRESS => {
+                self.tma = value;
+                if self.reloaded {
+                    self.tima = value;
+                }
+            }
+            _ => self.tac = value & 0x07,
+        }
+
+        // Resetting DIV or changing TAC can produce a falling edge.
+        if before && !self.signal() {
+            self.increment();
+        }
+    }
+
+    /// The counter bit TIMA is clocked from, for each TAC frequency.
+    fn selected_bit(&self) -> u16 {
+        match self.tac & 0x03 {
+            0 => 1 << 9, // 4096 Hz
+            1 => 1 << 3, // 262144 Hz
+            2 => 1 << 5, // 65536 Hz
+            _ => 1 << 7, // 16384 Hz
+        }
+    }
+
+    fn signal(&self) -> bool {
+        self.tac & TAC_ENABLE != 0 && self.counter & self.selected_bit() != 0
+    }
+
+    fn increment(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        if overflow {
+            self.overflowed = true;
+        }
+    }
+}
+
+// Tick for the given number of M-cycles, returning how many timer
+// interrupts were requested.
+#[cfg(test)]
+fn tick_cycles(timer: &mut Timer, cycles: u32) -> u32 {
+    (0..cycles).filter(|_| timer.tick()).count() as u32
+}
+
+#[test]
+fn div_increments_every_64_cycles() {
+    let mut timer = Timer::new();
+    tick_cycles(&mut timer, 63);
+    assert_eq!(timer.read(DIV_ADDRESS), 0);
+    tick_cycles(&mut timer, 1);
+    assert_eq!(timer.read(DIV_ADDRESS), 1);
+
+    timer.write(DIV_ADDRESS, 0x42);
+    assert_eq!(timer.read(DIV_ADDRESS), 0);
+}
+
+#[test]
+fn tima_frequencies() {
+    // (TAC, M-cycles per increment)
+    let frequencies = [(0x04, 256), (0x05, 4), (0x06, 16), (0x07, 64)];
+    for &(tac, period) in frequencies.iter() {
+        let mut timer = Timer::new();
+        timer.write(TAC_ADDRESS, tac);
+        tick_cycles(&mut timer, period - 1);
+        assert_eq!(timer.read(TIMA_ADDRESS), 0, "TAC {:02X}", tac);
+        tick_cycles(&mut timer, 1);
+        assert_eq!(timer.read(TIMA_ADDRESS), 1, "TAC {:02X}", tac);
+        tick_cycles(&mut timer, period * 3);
+        assert_eq!(timer.read(TIMA_ADDRESS), 4, "TAC {:02X}", tac);
+    }
+}
+
+#[test]
+fn tima_disabled() {
+    let mut timer = Timer::new();
+    timer.write(TAC_ADDRESS, 0x01);
+    tick_cycles(&mut timer, 100);
+    assert_eq!(timer.read(TIMA_ADDRESS), 0);
+    assert_eq!(timer.read(TAC_ADDRESS), 0xF9);
+}
+
+#[test]
+fn tima_overflow_reload_is_delayed() {
+    let mut timer = Timer::new();
+    timer.write(TMA_ADDRESS, 0x80);
+    timer.write(TIMA_ADDRESS, 0xFF);
+    timer.write(TAC_ADDRESS, 0x05);
+
+    assert_eq!(tick_cycles(&mut timer, 4), 0);
+    // TIMA reads 0 for a cycle before the reload.
+    assert_eq!(timer.read(TIMA_ADDRESS), 0x00);
+
+    assert!(timer.tick());
+    assert_eq!(timer.read(TIMA_ADDRESS), 0x80);
+}
+
+#[test]
+fn tima_write_cancels_reload() {
+    let mut timer = Timer::new();
+    timer.write(TMA_ADDRESS, 0x80);
+    timer.write(TIMA_ADDRESS, 0xFF);
+    timer.write(TAC_ADDRESS, 0x05);
+    tick_cycles(&mut timer, 4);
+
+    timer.write(TIMA_ADDRESS, 0x10);
+    assert!(!timer.tick());
+    assert_eq!(timer.read(TIMA_ADDRESS), 0x10);
+}
+
+#[test]
+fn tima_write_ignored_during_reload() {
+    let mut timer = Timer::new();
+    timer.write(TMA_ADDRESS, 0x80);
+    timer.write(TIMA_ADDRESS, 0xFF);
+    timer.write(TAC_ADDRESS, 0x05);
+    tick_cycles(&mut timer, 5);
+
+    timer.write(TIMA_ADDRESS, 0x10);
+    assert_eq!(timer.read(TIMA_ADDRESS), 0x80);
+
+    // But TMA writes go straight through to TIMA.
+    timer.write(TMA_ADDRESS, 0x20);
+    assert_eq!(timer.read(TIMA_ADDRESS), 0x20);
+
+    timer.tick();
+    timer.write(TMA_ADDRESS, 0x30);
+    assert_eq!(timer.read(TIMA_ADDRESS), 0x20);
+}
+
+#[test]
+fn div_write_glitch() {
+    let mut timer = Timer::new();
+    timer.write(TAC_ADDRESS, 0x05);
+    // Bit 3 of the counter is set after two M-cycles.
+    tick_cycles(&mut timer, 2);
+    assert_eq!(timer.read(TIMA_ADDRESS), 0);
+
+    timer.write(DIV_ADDRESS, 0x00);
+    assert_eq!(timer.read(TIMA_ADDRESS), 1);
+
+    // Resetting with the bit clear doesn't increment.
+    tick_cycles(&mut timer, 1);
+    timer.write(DIV_ADDRESS, 0x00);
+    assert_eq!(timer.read(TIMA_ADDRESS), 1);
+}
+
+#[test]
+fn tac_change_glitch() {
+    let mut timer = Timer::new();
+    timer.write(TAC_ADDRESS, 0x05);
+    tick_cycles(&mut timer, 2);
+
+    // Disabling the timer while the selected bit is set is a falling
+    // edge.
+    timer.write(TAC_ADDRESS, 0x01);
+    assert_eq!(timer.read(TIMA_ADDRESS), 1);
+
+    // So is switching to a frequency whose bit is clear.
+    timer.write(TAC_ADDRESS, 0x05);
+    timer.write(TAC_ADDRESS, 0x04);
+    assert_eq!(timer.read(TIMA_ADDRESS), 2);
+}